@@ -1,5 +1,8 @@
 use clap::Parser;
 
+use crate::local::DEFAULT_PATTERNS;
+use crate::storage::StorageKind;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct FeedConfig {
@@ -18,4 +21,72 @@ pub struct FeedConfig {
     /// Port to bind
     #[arg(short, long, default_value = "7531")]
     pub port: u16,
+
+    /// Regex pattern of todo markers, matched against comment text. Can be
+    /// given multiple times
+    #[arg(long = "pattern", default_values_t = default_patterns())]
+    pub patterns: Vec<String>,
+
+    /// Number of lines captured before and after each todo
+    #[arg(long, default_value = "3")]
+    pub context_lines: usize,
+
+    /// Number of threads to diff commits with
+    #[arg(long, default_value_t = default_workers())]
+    pub workers: usize,
+
+    /// Glob of paths to scan. Everything is scanned if not given. Can be given
+    /// multiple times
+    #[arg(long = "include")]
+    pub include: Vec<String>,
+
+    /// Glob of paths not to scan. Can be given multiple times
+    #[arg(long = "exclude")]
+    pub exclude: Vec<String>,
+
+    /// Blobs larger than this many bytes are not scanned
+    #[arg(long, default_value = "1048576")]
+    pub max_blob_size: usize,
+
+    /// File of author aliases in `.mailmap` format, applied on top of each
+    /// repo's `.mailmap`
+    #[arg(long)]
+    pub alias_file: Option<String>,
+
+    /// Maximal number of rows in one insert. Also the number of rows
+    /// buffered before the walk waits for the database. SQL databases get
+    /// smaller statements if it binds more values than they take
+    #[arg(long, default_value = "500")]
+    pub insert_batch_size: usize,
+
+    /// Number of update jobs run at a time. Others wait in the queue
+    #[arg(long, default_value = "1")]
+    pub max_jobs: usize,
+
+    /// Where rows are stored, one of {greptime, sql, sqlite}. `sql` connects
+    /// to the MySQL or Postgres url in environment variable `DATABASE_URL`
+    #[arg(long, value_enum, default_value_t = StorageKind::Sql)]
+    pub storage: StorageKind,
+
+    /// Address of the GreptimeDB HTTP API
+    #[arg(long, default_value = "http://localhost:4000")]
+    pub greptime_url: String,
+
+    /// Database to write to through the GreptimeDB HTTP API
+    #[arg(long, default_value = "public")]
+    pub greptime_db: String,
+
+    /// SQLite file to store rows in, created if missing
+    #[arg(long, default_value = "/tmp/greptodo.db")]
+    pub sqlite_path: String,
+}
+
+fn default_patterns() -> Vec<String> {
+    DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect()
+}
+
+fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
 }
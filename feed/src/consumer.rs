@@ -1,62 +1,325 @@
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::schema::Record;
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tracing::info;
+
+use crate::error::FeedResult;
+use crate::schema::{Lifecycle, OpenLifecycle, Record, Table, Value, LIFECYCLES, RECORDS};
+use crate::storage::Storage;
 
 pub trait Consumer {
     fn record(&self, record: Record);
+
+    fn lifecycle(&self, lifecycle: Lifecycle);
+
+    /// Whether the consumer stopped taking data, like when its writer failed.
+    /// The walk is stopped early then.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Called once the commits to walk are known, and after each of them.
+    fn progress(&self, _progress: WalkProgress) {}
+}
+
+/// How far a walk is.
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkProgress {
+    /// The commit just walked, `None` before the first one
+    pub commit_id: Option<String>,
+    pub walked: usize,
+    pub total: usize,
 }
 
+/// A todo change, as reported while the walk goes.
+#[derive(Debug, Clone, Serialize)]
+pub struct FoundRecord {
+    pub commit_id: String,
+    pub operation: String,
+    pub file_path: Option<String>,
+    pub content: String,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ProgressEvent {
+    Commit(WalkProgress),
+    Record(FoundRecord),
+}
+
+/// Number of events buffered for each listener of [Progress::events]. A
+/// listener that falls further behind misses the oldest ones.
+const PROGRESS_EVENTS_CAPACITY: usize = 1024;
+
 pub struct PrintConsumer {}
 
 impl Consumer for PrintConsumer {
     fn record(&self, record: Record) {
         println!("{:?}", record);
     }
+
+    fn lifecycle(&self, lifecycle: Lifecycle) {
+        println!("{:?}", lifecycle);
+    }
 }
 
-#[derive(Default)]
+/// One row of insert data, in the column order of its [Table].
+#[derive(Debug)]
+pub enum Row {
+    Record(Vec<Value>),
+    Lifecycle(Vec<Value>),
+    /// Open lifecycle row to delete, as it is replaced by the next written
+    /// one
+    ClosedStored(OpenLifecycle),
+}
+
+/// Number of rows written to each table.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RowsWritten {
+    pub records: usize,
+    pub lifecycles: usize,
+}
+
+/// Counters of a running walk, read by others while it goes.
+#[derive(Debug)]
+pub struct Progress {
+    pub commits_walked: AtomicUsize,
+    pub commits_total: AtomicUsize,
+    pub records_found: AtomicUsize,
+    /// Each walked commit and found record, for whoever listens
+    pub events: broadcast::Sender<ProgressEvent>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            commits_walked: AtomicUsize::default(),
+            commits_total: AtomicUsize::default(),
+            records_found: AtomicUsize::default(),
+            events: broadcast::channel(PROGRESS_EVENTS_CAPACITY).0,
+        }
+    }
+}
+
+/// Hand rows over to [write_rows] while the walk is running. The channel is
+/// bounded, so the walk blocks when the database falls behind.
 pub struct DatabaseConsumer {
-    rows: Arc<Mutex<Vec<String>>>,
+    sender: mpsc::Sender<Row>,
+    progress: Arc<Progress>,
 }
 
 impl Consumer for DatabaseConsumer {
     fn record(&self, record: Record) {
-        let new_row = Self::format_record(record);
-        let mut rows = self.rows.lock().unwrap();
-        rows.push(new_row);
+        self.progress.records_found.fetch_add(1, Ordering::Relaxed);
+        // nobody may be listening
+        let _ = self
+            .progress
+            .events
+            .send(ProgressEvent::Record(FoundRecord {
+                commit_id: record.commit_id.clone(),
+                operation: record.operation.to_string(),
+                file_path: record.file_path.clone(),
+                content: record.content.clone(),
+                link: record.link.clone(),
+            }));
+        // a closed channel means the writer failed, which it reports itself
+        let _ = self
+            .sender
+            .blocking_send(Row::Record(Self::record_values(record)));
+    }
+
+    fn lifecycle(&self, lifecycle: Lifecycle) {
+        // the stored row may have the same key, so it goes first
+        if let (Some(introduced), true) = (&lifecycle.introduced, lifecycle.closes_stored) {
+            let _ = self.sender.blocking_send(Row::ClosedStored(OpenLifecycle {
+                repo_name: lifecycle.repo_name.clone(),
+                ref_name: lifecycle.ref_name.clone(),
+                file_path: lifecycle.file_path.clone(),
+                content: lifecycle.content.clone(),
+                introduced: introduced.clone(),
+                latest_file_path: lifecycle.latest_file_path.clone(),
+                latest_content: lifecycle.latest_content.clone(),
+            }));
+        }
+        let _ = self
+            .sender
+            .blocking_send(Row::Lifecycle(Self::lifecycle_values(lifecycle)));
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    fn progress(&self, progress: WalkProgress) {
+        self.progress
+            .commits_walked
+            .store(progress.walked, Ordering::Relaxed);
+        self.progress
+            .commits_total
+            .store(progress.total, Ordering::Relaxed);
+        let _ = self.progress.events.send(ProgressEvent::Commit(progress));
     }
 }
 
 impl DatabaseConsumer {
-    pub fn new() -> Self {
-        Self {
-            rows: Arc::new(Mutex::new(Vec::new())),
-        }
+    /// Create a consumer and the receiving end to pass to [write_rows]. At
+    /// most `capacity` rows are waiting to be written at a time.
+    pub fn new(capacity: usize, progress: Arc<Progress>) -> (Self, mpsc::Receiver<Row>) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        (Self { sender, progress }, receiver)
     }
 
-    /// Format a [Record] to one line of insert data
+    /// Values of a [Record], in the columns of [RECORDS]
     ///
     /// The last field `calc_time` is filled by default value (current timestamp)
-    fn format_record(record: Record) -> String {
-        format!(
-            "(\'{}\',\'{}\',\'{}\',\'{}\',\'{}\',\'{}\',\'{}\',\'{}\',\'{}\')",
-            record.repo_name,
-            record.commit_time,
-            record.author_name,
-            record.author_email,
-            record.operation,
-            record.file_path.unwrap_or_else(|| String::new()),
-            record.commit_id,
-            record.commit_messaage.replace('\'', "_").replace('\"', "_"),
-            record.content.replace('\'', "_").replace('\"', "_"),
-        )
-    }
-
-    pub fn into_insert(self) -> String {
-        let rows = self.rows.lock().unwrap();
-        let mut insert = String::from("INSERT INTO `records` (`repo_name`, `commit_time`, `author_name`, `author_email`, `operation`, `file_path`, `commit_id`, `commit_message`, `content`) VALUES ");
-        insert.push_str(&rows.join(","));
-        insert.push(';');
-        insert
+    fn record_values(record: Record) -> Vec<Value> {
+        vec![
+            Value::Text(record.repo_name),
+            time_value(Some(&record.commit_time)),
+            offset_value(&record.commit_time),
+            time_value(Some(&record.author_time)),
+            offset_value(&record.author_time),
+            Value::Text(record.author_name),
+            Value::Text(record.author_email),
+            Value::Text(record.raw_author_name),
+            Value::Text(record.raw_author_email),
+            Value::Text(record.operation.to_string()),
+            Value::Text(record.file_path.unwrap_or_default()),
+            Value::Text(record.commit_id),
+            Value::Text(record.commit_messaage),
+            Value::Text(record.content),
+            Value::Text(record.pattern),
+            Value::Text(record.language),
+            Value::Text(record.marker),
+            Value::Text(record.assignee.unwrap_or_default()),
+            Value::Text(record.issues.join(",")),
+            // midnight of the day, so todos past it are `deadline < now()`
+            Value::Time(record.deadline.and_then(|date| date.and_hms_opt(0, 0, 0))),
+            Value::Text(record.message),
+            Value::Text(record.previous_file_path.unwrap_or_default()),
+            Value::Text(record.previous_content.unwrap_or_default()),
+            line_number_value(record.old_line_number),
+            line_number_value(record.new_line_number),
+            Value::Text(record.context),
+            Value::Text(record.link.unwrap_or_default()),
+            Value::Text(record.ref_name),
+            Value::Text(record.config_rev.unwrap_or_default()),
+        ]
+    }
+
+    /// Values of a [Lifecycle], in the columns of [LIFECYCLES]
+    ///
+    /// `first_seen_time` is the time index in GreptimeDB, so it only depends
+    /// on the todo, and writing a lifecycle again replaces the row.
+    fn lifecycle_values(lifecycle: Lifecycle) -> Vec<Value> {
+        let introduced = lifecycle.introduced.as_ref();
+        let resolved = lifecycle.resolved.as_ref();
+        let first_seen = introduced.or(resolved).map(|e| &e.commit_time);
+        vec![
+            Value::Text(lifecycle.repo_name),
+            Value::Text(lifecycle.ref_name),
+            Value::Text(lifecycle.file_path),
+            Value::Text(lifecycle.content),
+            Value::Text(introduced.map(|e| e.commit_id.clone()).unwrap_or_default()),
+            time_value(introduced.map(|e| &e.commit_time)),
+            Value::Text(
+                introduced
+                    .map(|e| e.author_name.clone())
+                    .unwrap_or_default(),
+            ),
+            Value::Text(resolved.map(|e| e.commit_id.clone()).unwrap_or_default()),
+            time_value(resolved.map(|e| &e.commit_time)),
+            Value::Text(resolved.map(|e| e.author_name.clone()).unwrap_or_default()),
+            Value::Int(lifecycle.age_secs),
+            time_value(first_seen),
+            Value::Text(lifecycle.latest_file_path),
+            Value::Text(lifecycle.latest_content),
+        ]
+    }
+}
+
+/// Insert rows from `receiver` until the consumer is dropped, with at most
+/// `batch_size` rows in one statement. Open lifecycles replaced by written
+/// ones are deleted right before the batch of their replacement, which may
+/// have the same key.
+pub async fn write_rows(
+    storage: &dyn Storage,
+    mut receiver: mpsc::Receiver<Row>,
+    batch_size: usize,
+) -> FeedResult<RowsWritten> {
+    let batch_size = batch_size.max(1);
+    let mut records = Vec::with_capacity(batch_size);
+    let mut lifecycles = Vec::with_capacity(batch_size);
+    let mut closed_stored = vec![];
+    let mut written = RowsWritten::default();
+
+    while let Some(row) = receiver.recv().await {
+        match row {
+            Row::Record(row) => {
+                records.push(row);
+                if records.len() >= batch_size {
+                    written.records += flush(storage, &RECORDS, &mut records).await?;
+                }
+            }
+            Row::Lifecycle(row) => {
+                lifecycles.push(row);
+                if lifecycles.len() >= batch_size {
+                    delete_closed(storage, &mut closed_stored).await?;
+                    written.lifecycles += flush(storage, &LIFECYCLES, &mut lifecycles).await?;
+                }
+            }
+            Row::ClosedStored(open) => closed_stored.push(open),
+        }
     }
+    written.records += flush(storage, &RECORDS, &mut records).await?;
+    delete_closed(storage, &mut closed_stored).await?;
+    written.lifecycles += flush(storage, &LIFECYCLES, &mut lifecycles).await?;
+
+    info!("wrote {written:?}");
+    Ok(written)
+}
+
+/// Insert and clear the buffered rows, returning how many are written. Rows
+/// already stored are skipped and not counted. Nothing is sent if there are
+/// none.
+async fn flush(
+    storage: &dyn Storage,
+    table: &Table,
+    rows: &mut Vec<Vec<Value>>,
+) -> FeedResult<usize> {
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let rows = std::mem::take(rows);
+    let written = storage.insert(table, rows).await?;
+    Ok(written as usize)
+}
+
+/// Delete and clear the stored open lifecycles replaced by buffered rows.
+async fn delete_closed(storage: &dyn Storage, closed: &mut Vec<OpenLifecycle>) -> FeedResult<()> {
+    if closed.is_empty() {
+        return Ok(());
+    }
+    storage.delete_open_lifecycles(closed).await?;
+    closed.clear();
+    Ok(())
+}
+
+fn time_value(time: Option<&DateTime<FixedOffset>>) -> Value {
+    Value::Time(time.map(|time| time.naive_utc()))
+}
+
+/// Line numbers are part of the primary key of `records`, which can't hold
+/// nulls. They start from 1, so 0 stands for the side a change doesn't have.
+fn line_number_value(line_number: Option<u32>) -> Value {
+    Value::Int(Some(line_number.map_or(0, i64::from)))
+}
+
+/// UTC offset in seconds
+fn offset_value(time: &DateTime<FixedOffset>) -> Value {
+    Value::Int(Some(time.offset().local_minus_utc().into()))
 }
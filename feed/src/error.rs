@@ -37,6 +37,100 @@ pub enum Error {
         source: Box<gix::discover::Error>,
     },
 
+    #[snafu(display("At {location}. Failed to resolve revision {rev}: {source}"))]
+    ResolveRevision {
+        rev: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Revision {rev} not found"))]
+    RevisionNotFound { rev: String, location: Location },
+
+    #[snafu(display("At {location}. Failed to read object: {source}"))]
+    ReadObject {
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Object {id} is not a commit: {source}"))]
+    NotCommit {
+        id: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Failed to traverse tree: {source}"))]
+    TraverseTree {
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Failed to walk history: {source}"))]
+    WalkHistory {
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Malformed author of commit {commit}: {source}"))]
+    MalformedAuthor {
+        commit: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Malformed time of commit {commit}: {source}"))]
+    MalformedCommitTime {
+        commit: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display(
+        "At {location}. Time {seconds} with offset {offset} of commit {commit} is out of range"
+    ))]
+    InvalidCommitTime {
+        commit: String,
+        seconds: i64,
+        offset: i32,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Malformed message of commit {commit}: {source}"))]
+    MalformedMessage {
+        commit: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Failed to read tree of commit {commit}: {source}"))]
+    ReadTree {
+        commit: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Failed to read blob of {path}: {source}"))]
+    ReadBlob {
+        path: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Failed to diff commit {commit}: {source}"))]
+    DiffTree {
+        commit: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Worker diffing commits {first}..{last} panicked"))]
+    DiffWorkerPanicked {
+        first: String,
+        last: String,
+        location: Location,
+    },
+
     #[snafu(display("At {location}. Failed to run command `{command}`: {source}"))]
     RunCommand {
         command: String,
@@ -77,6 +171,24 @@ pub enum Error {
     #[snafu(display("At {location}. Invalid number: {number}"))]
     InvalidNumber { number: String, location: Location },
 
+    #[snafu(display("At {location}. Invalid todo pattern: {source}"))]
+    InvalidPattern {
+        source: regex::Error,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Invalid .greptodo.toml: {source}"))]
+    InvalidRepoConfig {
+        source: toml::de::Error,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Invalid path glob: {source}"))]
+    InvalidGlob {
+        source: globset::Error,
+        location: Location,
+    },
+
     #[snafu(display("At {location}. Failed to connect to database: {source}"))]
     DatabaseConnect {
         source: sqlx::Error,
@@ -88,6 +200,31 @@ pub enum Error {
         source: sqlx::Error,
         location: Location,
     },
+
+    #[snafu(display("At {location}. DATABASE_URL is not set"))]
+    MissingDatabaseUrl { location: Location },
+
+    #[snafu(display("At {location}. Unsupported database {scheme}"))]
+    UnsupportedDatabase { scheme: String, location: Location },
+
+    #[snafu(display("At {location}. HTTP request failed: {source}"))]
+    HttpRequest {
+        source: reqwest::Error,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. Failed to decode response: {source}"))]
+    DecodeResponse {
+        source: serde_json::Error,
+        location: Location,
+    },
+
+    #[snafu(display("At {location}. GreptimeDB request failed with status {status}: {error}"))]
+    GreptimeRequest {
+        status: u16,
+        error: String,
+        location: Location,
+    },
 }
 
 pub fn boxed<E: std::error::Error + Send + Sync + 'static>(
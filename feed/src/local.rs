@@ -1,34 +1,162 @@
-use std::sync::OnceLock;
+mod blame;
+mod filter;
+mod identity;
+mod lang;
+mod lifecycle;
+mod line_diff;
+mod repo_config;
+mod similar;
+mod todo;
 
-use gix::date::time::Format;
-use gix::object::tree::diff::{Action, Change};
-use gix::ThreadSafeRepository;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, FixedOffset, TimeZone};
+use gix::bstr::{BStr, ByteSlice};
+use gix::object::tree::diff::change::Event;
+use gix::object::tree::diff::rewrites::Copies;
+use gix::object::tree::diff::{Action, Change, Rewrites};
+use gix::object::Kind;
+use gix::traverse::tree::Recorder;
+use gix::{Commit, Repository, ThreadSafeRepository};
 use gix_hash::ObjectId;
-use regex::bytes::Regex;
-use snafu::ResultExt;
-use tracing::info;
+use gix_object::TreeRefIter;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+use tracing::{info, warn};
+
+pub use self::blame::Attribution;
+pub use self::identity::Identity;
+use self::identity::IdentityMap;
+use self::lang::Language;
+use self::lifecycle::LifecycleTracker;
+use self::repo_config::{RepoConfig, ScanConfig, CONFIG_FILE};
+pub use self::todo::Todo;
+use crate::consumer::{Consumer, WalkProgress};
+use crate::error::{
+    boxed, DiffTreeSnafu, DiffWorkerPanickedSnafu, Error, FeedResult, FileSystemSnafu,
+    InvalidCommitTimeSnafu, MalformedAuthorSnafu, MalformedCommitTimeSnafu, MalformedMessageSnafu,
+    NotCommitSnafu, OpenRepoSnafu, ReadBlobSnafu, ReadObjectSnafu, ReadTreeSnafu,
+    ResolveRevisionSnafu, TraverseTreeSnafu, WalkHistorySnafu,
+};
+use crate::schema::{CommitTimes, OpenLifecycle, Operation, Record, RecordBuilder, SnapshotTodo};
+
+/// Patterns used when neither the request nor the server config gives any.
+///
+/// Patterns are matched against the text inside a comment, with the comment
+/// delimiter already stripped.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    "(?i)^\\W*todo\\b",
+    "(?i)^\\W*fixme\\b",
+    "(?i)^\\W*hack\\b",
+    "(?i)^\\W*xxx\\b",
+];
+
+/// How the history is walked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraversalMode {
+    /// Only follow the first parent of each commit. Changes from merged
+    /// branches are attributed to the merge commit.
+    #[default]
+    FirstParent,
+    /// Visit every commit reachable from the start. Merge commits only
+    /// record the changes they introduced themselves.
+    All,
+}
+
+impl TraversalMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TraversalMode::FirstParent => "first_parent",
+            TraversalMode::All => "all",
+        }
+    }
+}
+
+/// What to do with a commit that can't be read or diffed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Skip the commit and report it in the [FetchSummary]
+    #[default]
+    Skip,
+    /// Fail the whole fetch
+    Abort,
+}
 
-use crate::consumer::Consumer;
-use crate::error::{FeedResult, OpenRepoSnafu};
-use crate::schema::{Operation, RecordBuilder};
+/// Outcome of [FetchTask::execute].
+#[derive(Debug, Default, Serialize)]
+pub struct FetchSummary {
+    /// Commits walked, including skipped ones
+    pub num_commits: usize,
+    pub skipped: Vec<SkippedCommit>,
+    /// Why the walk stopped before reaching the end of history, like at the
+    /// boundary of a shallow clone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedCommit {
+    pub commit_id: String,
+    pub reason: String,
+}
 
-pub const DEFAULT_REGEXS: &[&str] = &["(?i)//\\s*todo"];
-static RE: OnceLock<Regex> = OnceLock::new();
+/// Number of commits each worker diffs before results are handed over to the
+/// consumer.
+const COMMITS_PER_WORKER: usize = 64;
 
 #[derive(Debug)]
 pub struct FetchRequest {
     /// Root path to the project. Parent path of `.git`
     pub root: String,
-    /// The default path of one project
-    pub branch: String,
+    /// Revision to start walking from. Anything `git rev-parse` understands,
+    /// like a branch, tag or commit
+    pub rev: String,
+    /// Name of the scanned ref, stored on each record so several refs of one
+    /// repo can be tracked side by side
+    pub ref_name: String,
     pub since: Option<ObjectId>,
     pub repo: String,
+    /// Regex patterns of todo markers, matched against comment text. Each
+    /// record is tagged with the first pattern that matches it.
+    pub patterns: Vec<String>,
+    /// Whether the request gave `patterns` itself instead of taking the
+    /// server's. They win over the markers of a repo's config then
+    pub patterns_requested: bool,
+    /// Number of lines captured before and after each todo
+    pub context_lines: usize,
+    /// Web url of the repository like `https://github.com/{org}/{repo}`, used
+    /// to build links to todos
+    pub remote: Option<String>,
+    pub traversal: TraversalMode,
+    /// Number of threads to diff commits with
+    pub workers: usize,
+    pub on_error: ErrorPolicy,
+    /// Globs of paths to scan. Everything is scanned if empty
+    pub include: Vec<String>,
+    /// Globs of paths not to scan
+    pub exclude: Vec<String>,
+    /// Blobs larger than this many bytes are not scanned
+    pub max_blob_size: usize,
+    /// File of author aliases in `.mailmap` format, applied on top of the
+    /// repo's `.mailmap`
+    pub alias_file: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct FetchTask {
     repo: ThreadSafeRepository,
     since: Option<ObjectId>,
+    /// `.gitattributes` at the scanned revision, applied to the whole history
+    gitattributes: Option<Vec<u8>>,
+    /// Settings of commits without a `.greptodo.toml`
+    default_config: Arc<ScanConfig>,
+    /// Settings by blob id of `.greptodo.toml`
+    configs: Mutex<HashMap<ObjectId, Arc<ScanConfig>>>,
+    identities: IdentityMap,
     req: FetchRequest,
 }
 
@@ -40,143 +168,851 @@ impl FetchTask {
                 path: req.root.clone(),
             })?;
 
-        // initialize the regex
-        RE.get_or_init(|| Regex::new(DEFAULT_REGEXS[0]).unwrap());
+        let tls_repo = repo.to_thread_local();
+        let start = resolve_commit(&tls_repo, &req.rev)?;
+        let gitattributes = read_root_file(&tls_repo, &start, ".gitattributes")?;
+        let default_config = ScanConfig::new(&req, gitattributes.as_deref(), None)?;
+        let aliases = match &req.alias_file {
+            Some(path) => Some(std::fs::read(path).context(FileSystemSnafu)?),
+            None => None,
+        };
+        let identities = IdentityMap::new(&tls_repo, aliases.as_deref());
 
         Ok(Self {
             repo,
             since: req.since,
+            gitattributes,
+            default_config: Arc::new(default_config),
+            configs: Mutex::new(HashMap::new()),
+            identities,
             req,
         })
     }
 
-    pub fn execute(&self, consumer: &dyn Consumer) -> FeedResult<()> {
+    /// Walk the history, handing records and lifecycles to `consumer`.
+    /// `stored_open` are the todos of the ref an earlier walk left open.
+    pub fn execute(
+        &self,
+        consumer: &dyn Consumer,
+        stored_open: Vec<OpenLifecycle>,
+    ) -> FeedResult<FetchSummary> {
         info!("executing request: {:?}", self.req);
 
+        let tracker = LifecycleTracker::new(
+            consumer,
+            self.req.repo.clone(),
+            self.req.ref_name.clone(),
+            stored_open,
+        );
         let tls_repo = self.repo.to_thread_local();
-        let head_ref = tls_repo.head_ref().unwrap().unwrap();
+        let head_id = self.resolve_start(&tls_repo)?.id;
 
-        let mut curr_id = head_ref.id();
-        loop {
-            let mut ancestor = curr_id.ancestors().first_parent_only().all().unwrap();
-            let parent = if let Some(Ok(parent)) = ancestor.nth(1) {
-                parent
-            } else {
+        let mut summary = FetchSummary::default();
+        let commits = self.commits_to_walk(&tls_repo, head_id, &mut summary)?;
+        consumer.progress(WalkProgress {
+            commit_id: None,
+            walked: 0,
+            total: commits.len(),
+        });
+        let batch_size = self.req.workers.max(1) * COMMITS_PER_WORKER;
+        for batch in commits.chunks(batch_size) {
+            if consumer.is_closed() {
+                warn!("consumer is closed, stopping the walk");
                 break;
-            };
+            }
+            for (commit_id, result) in self.diff_in_parallel(batch) {
+                summary.num_commits += 1;
+                match result {
+                    Ok(Some(commit_changes)) => commit_changes.emit(&tracker),
+                    // root commit
+                    Ok(None) => {}
+                    Err(e) if self.req.on_error == ErrorPolicy::Skip => {
+                        warn!("skipping commit {commit_id}: {e}");
+                        summary.skipped.push(SkippedCommit {
+                            commit_id: commit_id.to_string(),
+                            reason: e.to_string(),
+                        });
+                    }
+                    Err(e) => return Err(e),
+                }
+                consumer.progress(WalkProgress {
+                    commit_id: Some(commit_id.to_string()),
+                    walked: summary.num_commits,
+                    total: commits.len(),
+                });
+            }
+        }
+        tracker.finish();
 
-            // get parent tree to compute diff
-            let parent_tree = parent.object().unwrap().into_commit().tree().unwrap();
-            let commit = curr_id.object().unwrap().into_commit();
-            let commit_id = curr_id.detach().to_string();
-
-            // read commit info
-            let author = commit.author().unwrap();
-            let base_record = RecordBuilder::new_base(
-                self.req.repo.clone(),
-                commit.time().unwrap().format(Format::Unix),
-                author.name.to_string(),
-                author.email.to_string(),
-                commit_id,
-                commit.message().unwrap().title.to_string(),
-            );
+        Ok(summary)
+    }
 
-            // get and process diff
-            let tree = commit.tree().unwrap();
-            let _changes = parent_tree
-                .changes()
-                .unwrap()
-                .for_each_to_obtain_tree(&tree, |changes| {
-                    self.process_diff(&base_record, consumer, changes)
-                });
+    /// Diff commits on up to `workers` threads, each with its own repository
+    /// handle. Results are in the same order as `commits`.
+    fn diff_in_parallel(
+        &self,
+        commits: &[ObjectId],
+    ) -> Vec<(ObjectId, FeedResult<Option<CommitChanges>>)> {
+        let workers = self.req.workers.max(1);
+        let chunk_size = ((commits.len() + workers - 1) / workers).max(1);
 
-            // stop on the given commit.
-            if Some(curr_id.detach()) == self.since {
-                break;
+        std::thread::scope(|scope| {
+            let handles = commits
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let handle = scope.spawn(move || {
+                        let repo = self.repo.to_thread_local();
+                        chunk
+                            .iter()
+                            .map(|commit_id| (*commit_id, self.diff_any(&repo, *commit_id)))
+                            .collect::<Vec<_>>()
+                    });
+                    (chunk, handle)
+                })
+                .collect::<Vec<_>>();
+
+            let mut result = Vec::with_capacity(commits.len());
+            for (chunk, handle) in handles {
+                match handle.join() {
+                    Ok(diffs) => result.extend(diffs),
+                    // results of the whole chunk are lost
+                    Err(_) => result.extend(chunk.iter().map(|commit_id| {
+                        let error = DiffWorkerPanickedSnafu {
+                            first: chunk[0].to_string(),
+                            last: chunk[chunk.len() - 1].to_string(),
+                        }
+                        .build();
+                        (*commit_id, Err(error))
+                    })),
+                }
+            }
+            result
+        })
+    }
+
+    /// Diff a commit against its parents. `None` for the root commit.
+    ///
+    /// Following first parents only, the merged branches are never walked,
+    /// so a merge is diffed against its first parent to take in their
+    /// changes.
+    fn diff_any(
+        &self,
+        repo: &Repository,
+        commit_id: ObjectId,
+    ) -> FeedResult<Option<CommitChanges>> {
+        let parents = read_parents(repo, commit_id)?;
+        let commit_changes = match (self.req.traversal, parents.as_slice()) {
+            (_, []) => return Ok(None),
+            (TraversalMode::FirstParent, [first, ..]) | (TraversalMode::All, [first]) => {
+                self.diff_commit(repo, commit_id, *first)?
+            }
+            (TraversalMode::All, [first, others @ ..]) => {
+                self.diff_merge(repo, commit_id, *first, others)?
+            }
+        };
+        Ok(Some(commit_changes))
+    }
+
+    /// Commits to diff, from new to old with every commit before its
+    /// parents. Stops before `since`, which is already walked.
+    fn commits_to_walk(
+        &self,
+        repo: &Repository,
+        head: ObjectId,
+        summary: &mut FetchSummary,
+    ) -> FeedResult<Vec<ObjectId>> {
+        let head = read_commit(repo, head)?;
+        let mut commits = vec![];
+
+        match self.req.traversal {
+            TraversalMode::FirstParent => {
+                let ancestors = head
+                    .ancestors()
+                    .first_parent_only()
+                    .all()
+                    .map_err(boxed)
+                    .context(WalkHistorySnafu)?;
+                for id in ancestors {
+                    let id = match id.map_err(boxed).context(WalkHistorySnafu) {
+                        Ok(id) => id.detach(),
+                        Err(e) => {
+                            self.stop_walk(summary, e)?;
+                            break;
+                        }
+                    };
+                    if Some(id) == self.since {
+                        break;
+                    }
+                    commits.push(id);
+                }
+            }
+            TraversalMode::All => {
+                // everything reachable from `since` is already walked
+                let mut walked = HashSet::new();
+                if let Some(since) = self.since {
+                    let ancestors = read_commit(repo, since)?
+                        .ancestors()
+                        .all()
+                        .map_err(boxed)
+                        .context(WalkHistorySnafu)?;
+                    for id in ancestors {
+                        match id.map_err(boxed).context(WalkHistorySnafu) {
+                            Ok(id) => walked.insert(id.detach()),
+                            Err(e) => {
+                                self.stop_walk(summary, e)?;
+                                break;
+                            }
+                        };
+                    }
+                }
+
+                let ancestors = head
+                    .ancestors()
+                    .all()
+                    .map_err(boxed)
+                    .context(WalkHistorySnafu)?;
+                let mut found = vec![];
+                for id in ancestors {
+                    let id = match id.map_err(boxed).context(WalkHistorySnafu) {
+                        Ok(id) => id.detach(),
+                        Err(e) => {
+                            self.stop_walk(summary, e)?;
+                            break;
+                        }
+                    };
+                    if !walked.contains(&id) {
+                        found.push((id, read_parents(repo, id)?));
+                    }
+                }
+                commits = children_first(found);
             }
-            curr_id = parent;
         }
 
+        Ok(commits)
+    }
+
+    /// The walk can't go any further, like at the boundary of a shallow
+    /// clone. Keep what is walked so far unless told to abort.
+    fn stop_walk(&self, summary: &mut FetchSummary, error: Error) -> FeedResult<()> {
+        if self.req.on_error == ErrorPolicy::Abort {
+            return Err(error);
+        }
+        warn!("history walk stopped early: {error}");
+        summary.truncated = Some(error.to_string());
         Ok(())
     }
 
+    /// Diff a commit against one of its parents.
+    fn diff_commit(
+        &self,
+        repo: &Repository,
+        commit_id: ObjectId,
+        parent_id: ObjectId,
+    ) -> FeedResult<CommitChanges> {
+        let commit_hex = commit_id.to_string();
+
+        // get parent tree to compute diff
+        let parent_tree = read_commit(repo, parent_id)?
+            .tree()
+            .map_err(boxed)
+            .with_context(|_| ReadTreeSnafu {
+                commit: parent_id.to_string(),
+            })?;
+        let commit = read_commit(repo, commit_id)?;
+        let config = self.config_at(repo, &commit)?;
+
+        // read commit info
+        let author = commit
+            .author()
+            .map_err(boxed)
+            .with_context(|_| MalformedAuthorSnafu {
+                commit: &commit_hex,
+            })?;
+        let commit_time =
+            commit
+                .time()
+                .map_err(boxed)
+                .with_context(|_| MalformedCommitTimeSnafu {
+                    commit: &commit_hex,
+                })?;
+        let message = commit
+            .message()
+            .map_err(boxed)
+            .with_context(|_| MalformedMessageSnafu {
+                commit: &commit_hex,
+            })?;
+        let times = CommitTimes {
+            author_time: typed_time(&commit_hex, author.time)?,
+            commit_time: typed_time(&commit_hex, commit_time)?,
+        };
+        let (raw_author, author) = self.identities.resolve(author);
+        let base_record = RecordBuilder::new_base(
+            self.req.repo.clone(),
+            times,
+            author,
+            raw_author,
+            commit_hex.clone(),
+            parent_id.to_string(),
+            message.title.to_string(),
+        );
+
+        // get and process diff
+        let tree = commit
+            .tree()
+            .map_err(boxed)
+            .with_context(|_| ReadTreeSnafu {
+                commit: &commit_hex,
+            })?;
+        let mut commit_changes = CommitChanges::default();
+        parent_tree
+            .changes()
+            .map_err(boxed)
+            .with_context(|_| DiffTreeSnafu {
+                commit: &commit_hex,
+            })?
+            .track_path()
+            .track_rewrites(Some(Rewrites {
+                copies: Some(Copies::default()),
+                ..Default::default()
+            }))
+            .for_each_to_obtain_tree(&tree, |changes| {
+                self.process_diff(&base_record, &config, &mut commit_changes, changes)
+            })
+            .map_err(boxed)
+            .with_context(|_| DiffTreeSnafu {
+                commit: &commit_hex,
+            })?;
+
+        Ok(commit_changes)
+    }
+
+    /// Diff a merge commit. Changes brought in by the merged branches are
+    /// already recorded on their own commits, so only changes that differ
+    /// from every parent, like conflict resolutions, are kept. They are
+    /// recorded as against the first parent.
+    fn diff_merge(
+        &self,
+        repo: &Repository,
+        commit_id: ObjectId,
+        first_parent: ObjectId,
+        other_parents: &[ObjectId],
+    ) -> FeedResult<CommitChanges> {
+        let mut commit_changes = self.diff_commit(repo, commit_id, first_parent)?;
+        for parent in other_parents {
+            if commit_changes.records.is_empty() && commit_changes.renames.is_empty() {
+                break;
+            }
+            let other = self.diff_commit(repo, commit_id, *parent)?;
+            let keys = other.records.iter().map(merge_key).collect::<HashSet<_>>();
+            commit_changes
+                .records
+                .retain(|record| keys.contains(&merge_key(record)));
+            commit_changes
+                .renames
+                .retain(|rename| other.renames.contains(rename));
+        }
+
+        Ok(commit_changes)
+    }
+
+    /// Settings in effect at `commit`. An invalid `.greptodo.toml` is ignored
+    /// so it doesn't fail every commit it is in.
+    fn config_at(&self, repo: &Repository, commit: &Commit) -> FeedResult<Arc<ScanConfig>> {
+        let Some(id) = find_root_entry(commit, CONFIG_FILE)? else {
+            return Ok(self.default_config.clone());
+        };
+        if let Some(config) = self.configs.lock().unwrap().get(&id) {
+            return Ok(config.clone());
+        }
+
+        let data = read_blob(repo, id)?;
+        let config = match RepoConfig::parse(&data).and_then(|repo_config| {
+            ScanConfig::new(
+                &self.req,
+                self.gitattributes.as_deref(),
+                Some((id, repo_config)),
+            )
+        }) {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                warn!("ignoring {CONFIG_FILE} {id}: {e}");
+                self.default_config.clone()
+            }
+        };
+        self.configs.lock().unwrap().insert(id, config.clone());
+        Ok(config)
+    }
+
+    /// Resolve the requested revision to a commit.
+    fn resolve_start<'repo>(&self, repo: &'repo Repository) -> FeedResult<Commit<'repo>> {
+        resolve_commit(repo, &self.req.rev)
+    }
+
+    /// List all todos present in the tree of the requested revision.
+    ///
+    /// With `blame`, each todo is attributed to the commit that introduced it.
+    pub fn snapshot(&self, blame: bool) -> FeedResult<Vec<SnapshotTodo>> {
+        info!("taking snapshot of {} at {}", self.req.repo, self.req.rev);
+
+        let tls_repo = self.repo.to_thread_local();
+        let commit = self.resolve_start(&tls_repo)?;
+        let config = self.config_at(&tls_repo, &commit)?;
+        let tree = commit.tree().map_err(boxed).context(ReadObjectSnafu)?;
+
+        let mut recorder = Recorder::default();
+        tree.traverse()
+            .breadthfirst(&mut recorder)
+            .map_err(boxed)
+            .context(TraverseTreeSnafu)?;
+
+        let mut todos = vec![];
+        for entry in recorder.records {
+            if !entry.mode.is_no_tree() {
+                continue;
+            }
+            let file_path = entry.filepath.to_string();
+            let Some(language) = Language::detect(&file_path) else {
+                continue;
+            };
+            if !config.filter.is_scanned(&file_path) {
+                continue;
+            }
+            let Ok(blob) = tls_repo.find_object(entry.oid) else {
+                continue;
+            };
+            if !config.filter.is_scanned_blob(&blob.data) {
+                continue;
+            }
+
+            for (line_number, line) in blob.data.lines().enumerate() {
+                let Some((index, comment)) = self.match_line(&config, language, line) else {
+                    continue;
+                };
+                let line_number = line_number + 1;
+                todos.push(SnapshotTodo {
+                    link: self.link(&commit.id.to_string(), &file_path, line_number as u32),
+                    file_path: file_path.clone(),
+                    line_number,
+                    language: language.name.to_string(),
+                    pattern: config.patterns[index].clone(),
+                    content: line.to_str_lossy().to_string(),
+                    todo: config.parse_todo(comment),
+                    introduced: None,
+                });
+            }
+        }
+
+        if blame {
+            let targets = todos
+                .iter()
+                .map(|todo| (todo.file_path.clone(), todo.line_number as u32 - 1))
+                .collect::<Vec<_>>();
+            let attributions = blame::blame(&tls_repo, &self.identities, commit.id, &targets)?;
+            for (todo, attribution) in todos.iter_mut().zip(attributions) {
+                todo.introduced = attribution;
+            }
+        }
+
+        Ok(todos)
+    }
+
     fn process_diff(
         &self,
         base_record: &RecordBuilder,
-        consumer: &dyn Consumer,
+        config: &ScanConfig,
+        commit_changes: &mut CommitChanges,
         changes: Change,
     ) -> FeedResult<Action> {
         let location = changes.location.to_string();
-        let location = if location.is_empty() {
-            None
-        } else {
-            Some(location)
-        };
+        if let Event::Rewrite {
+            source_location,
+            copy: false,
+            ..
+        } = &changes.event
+        {
+            commit_changes
+                .renames
+                .push((source_location.to_string(), location.clone()));
+        }
 
-        let diff = if let Some(Ok(diff)) = changes.event.diff() {
-            diff
+        // only files whose comment syntax is known are scanned
+        let language = if let Some(language) = Language::detect(&location) {
+            language
         } else {
             return Ok(Action::Continue);
         };
+        if !config.filter.is_scanned(&location) {
+            return Ok(Action::Continue);
+        }
+        let file = ScannedFile {
+            path: Some(location),
+            language,
+            config,
+        };
 
-        diff.lines(|changes| -> Result<(), !> {
-            let re = RE.get().unwrap();
-            match changes {
-                gix::object::blob::diff::line::Change::Addition { lines } => {
-                    for line in lines {
-                        if re.is_match(line) {
-                            let record = base_record.build(
-                                Operation::Add,
-                                location.clone(),
-                                line.to_string(),
-                            );
-                            consumer.record(record);
-                        }
-                    }
-                }
-                gix::object::blob::diff::line::Change::Deletion { lines } => {
-                    for line in lines {
-                        if re.is_match(line) {
-                            let record = base_record.build(
-                                Operation::Remove,
-                                location.clone(),
-                                line.to_string(),
-                            );
-                            consumer.record(record);
-                        }
-                    }
-                }
-                gix::object::blob::diff::line::Change::Modification {
-                    lines_before,
-                    lines_after,
-                } => {
-                    for line in lines_before {
-                        if re.is_match(line) {
-                            let record = base_record.build(
-                                Operation::Remove,
-                                location.clone(),
-                                line.to_string(),
-                            );
-                            consumer.record(record);
-                        }
-                    }
-                    for line in lines_after {
-                        if re.is_match(line) {
-                            let record = base_record.build(
-                                Operation::Add,
-                                location.clone(),
-                                line.to_string(),
-                            );
-                            consumer.record(record);
-                        }
-                    }
-                }
+        // whole files added, removed or copied
+        let (operation, id, source) = match &changes.event {
+            Event::Addition { entry_mode, id } if entry_mode.is_no_tree() => {
+                (Operation::Add, id, None)
             }
+            Event::Deletion { entry_mode, id } if entry_mode.is_no_tree() => {
+                (Operation::Remove, id, None)
+            }
+            Event::Rewrite {
+                source_location,
+                entry_mode,
+                id,
+                copy: true,
+                ..
+            } if entry_mode.is_no_tree() => (Operation::Add, id, Some(source_location.to_string())),
+            _ => return self.process_line_diff(base_record, commit_changes, changes, &file),
+        };
+        let blob = id.object().map_err(boxed).with_context(|_| ReadBlobSnafu {
+            path: changes.location.to_string(),
+        })?;
+        if !config.filter.is_scanned_blob(&blob.data) {
+            return Ok(Action::Continue);
+        }
+        let lines = blob
+            .data
+            .lines()
+            .map(ByteSlice::as_bstr)
+            .collect::<Vec<_>>();
+        let mut records =
+            self.match_lines(base_record, &file, operation, &lines, 0..lines.len() as u32);
+        for record in &mut records {
+            record.previous_file_path = source.clone();
+        }
+        commit_changes.records.extend(records);
 
-            Ok(())
-        })
-        .unwrap();
+        Ok(Action::Continue)
+    }
+
+    /// Process modified or renamed files by their line diff.
+    fn process_line_diff(
+        &self,
+        base_record: &RecordBuilder,
+        commit_changes: &mut CommitChanges,
+        changes: Change,
+        file: &ScannedFile,
+    ) -> FeedResult<Action> {
+        let (source, old_id, new_id) = match &changes.event {
+            Event::Modification {
+                previous_id, id, ..
+            } => (None, previous_id, id),
+            Event::Rewrite {
+                source_location,
+                source_id,
+                id,
+                ..
+            } => (Some(source_location.to_string()), source_id, id),
+            _ => return Ok(Action::Continue),
+        };
+        let path = changes.location.to_string();
+        let old_blob = old_id
+            .object()
+            .map_err(boxed)
+            .with_context(|_| ReadBlobSnafu { path: &path })?;
+        let new_blob = new_id
+            .object()
+            .map_err(boxed)
+            .with_context(|_| ReadBlobSnafu { path: &path })?;
+        if !file.config.filter.is_scanned_blob(&old_blob.data)
+            || !file.config.filter.is_scanned_blob(&new_blob.data)
+        {
+            return Ok(Action::Continue);
+        }
+        let old_lines = old_blob
+            .data
+            .lines()
+            .map(ByteSlice::as_bstr)
+            .collect::<Vec<_>>();
+        let new_lines = new_blob
+            .data
+            .lines()
+            .map(ByteSlice::as_bstr)
+            .collect::<Vec<_>>();
+
+        for (before, after) in line_diff::hunks(&old_blob.data, &new_blob.data) {
+            let removed =
+                self.match_lines(base_record, file, Operation::Remove, &old_lines, before);
+            let added = self.match_lines(base_record, file, Operation::Add, &new_lines, after);
+            let records = similar::pair_in_hunk(removed, added);
+            commit_changes
+                .records
+                .extend(records.into_iter().map(|mut record| {
+                    record.previous_file_path = source.clone();
+                    record
+                }));
+        }
 
         Ok(Action::Continue)
     }
+
+    /// Build one record for each line in `range` that has a comment matching
+    /// any of the patterns. `lines` are all lines of the file, from the old
+    /// side for removals and the new side for additions.
+    fn match_lines(
+        &self,
+        base_record: &RecordBuilder,
+        file: &ScannedFile,
+        operation: Operation,
+        lines: &[&BStr],
+        range: Range<u32>,
+    ) -> Vec<Record> {
+        let mut records = vec![];
+        for line_index in range {
+            let Some(line) = lines.get(line_index as usize) else {
+                break;
+            };
+            let Some((index, comment)) = self.match_line(file.config, file.language, line) else {
+                continue;
+            };
+            let mut record = base_record.build(
+                operation,
+                file.path.clone(),
+                file.language.name.to_string(),
+                file.config.patterns[index].clone(),
+                line.to_string(),
+                file.config.parse_todo(comment),
+            );
+
+            let line_number = line_index + 1;
+            let commit_id = if operation == Operation::Remove {
+                record.old_line_number = Some(line_number);
+                &base_record.parent_commit_id
+            } else {
+                record.new_line_number = Some(line_number);
+                &base_record.commit_id
+            };
+            record.ref_name = self.req.ref_name.clone();
+            record.config_rev = file.config.rev.clone();
+            record.context = self.context(lines, line_index as usize);
+            record.link = file
+                .path
+                .as_ref()
+                .and_then(|path| self.link(commit_id, path, line_number));
+            records.push(record);
+        }
+        records
+    }
+
+    /// Lines around the given line, including itself.
+    fn context(&self, lines: &[&BStr], index: usize) -> String {
+        let start = index.saturating_sub(self.req.context_lines);
+        let end = (index + self.req.context_lines + 1).min(lines.len());
+        lines[start..end]
+            .iter()
+            .map(|line| line.to_str_lossy())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Link to a line of the file on the remote, if the remote is known.
+    fn link(&self, commit_id: &str, path: &str, line_number: u32) -> Option<String> {
+        let remote = self.req.remote.as_ref()?;
+        Some(format!("{remote}/blob/{commit_id}/{path}#L{line_number}"))
+    }
+
+    /// Find the first comment in this line that matches any pattern. Returns
+    /// the index of the pattern and the comment text.
+    fn match_line<'a>(
+        &self,
+        config: &ScanConfig,
+        language: &Language,
+        line: &'a [u8],
+    ) -> Option<(usize, &'a [u8])> {
+        language.comments(line).into_iter().find_map(|comment| {
+            let index = config.regex.matches(comment).into_iter().next()?;
+            Some((index, comment))
+        })
+    }
+}
+
+fn read_commit(repo: &Repository, id: ObjectId) -> FeedResult<Commit<'_>> {
+    repo.find_object(id)
+        .map_err(boxed)
+        .context(ReadObjectSnafu)?
+        .try_into_commit()
+        .map_err(boxed)
+        .with_context(|_| NotCommitSnafu { id: id.to_string() })
+}
+
+/// Git time to a timestamp in the UTC offset it is recorded in.
+fn typed_time(commit: &str, time: gix::date::Time) -> FeedResult<DateTime<FixedOffset>> {
+    let seconds = time.seconds_since_unix_epoch as i64;
+    FixedOffset::east_opt(time.offset_in_seconds)
+        .and_then(|offset| offset.timestamp_opt(seconds, 0).single())
+        .with_context(|| InvalidCommitTimeSnafu {
+            commit,
+            seconds,
+            offset: time.offset_in_seconds,
+        })
+}
+
+fn resolve_commit<'repo>(repo: &'repo Repository, rev: &str) -> FeedResult<Commit<'repo>> {
+    Ok(repo
+        .rev_parse_single(rev)
+        .map_err(boxed)
+        .and_then(|id| id.object().map_err(boxed))
+        .and_then(|object| object.peel_to_kind(Kind::Commit).map_err(boxed))
+        .with_context(|_| ResolveRevisionSnafu { rev })?
+        .into_commit())
+}
+
+/// Blob id of a file at the root of the commit's tree, if there is one.
+fn find_root_entry(commit: &Commit, name: &str) -> FeedResult<Option<ObjectId>> {
+    let tree = commit.tree().map_err(boxed).context(ReadObjectSnafu)?;
+    Ok(TreeRefIter::from_bytes(&tree.data)
+        .filter_map(Result::ok)
+        .find(|entry| entry.filename == BStr::new(name) && entry.mode.is_no_tree())
+        .map(|entry| entry.oid.to_owned()))
+}
+
+fn read_blob(repo: &Repository, id: ObjectId) -> FeedResult<Vec<u8>> {
+    let blob = repo
+        .find_object(id)
+        .map_err(boxed)
+        .context(ReadObjectSnafu)?;
+    Ok(blob.data.clone())
+}
+
+/// Content of a file at the root of the commit's tree, if there is one.
+fn read_root_file(repo: &Repository, commit: &Commit, name: &str) -> FeedResult<Option<Vec<u8>>> {
+    match find_root_entry(commit, name)? {
+        Some(id) => read_blob(repo, id).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn read_parents(repo: &Repository, id: ObjectId) -> FeedResult<Vec<ObjectId>> {
+    Ok(read_commit(repo, id)?
+        .parent_ids()
+        .map(|id| id.detach())
+        .collect())
+}
+
+/// Order `commits`, given with their parents, so that every commit comes
+/// before its parents. The history walk visits the commits breadth first,
+/// which puts the parent of a long branch before the commits on it. Commits
+/// that are ready at the same time keep their order.
+fn children_first(commits: Vec<(ObjectId, Vec<ObjectId>)>) -> Vec<ObjectId> {
+    let order = commits.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    // children of each commit that are not sorted yet
+    let mut waiting = order
+        .iter()
+        .map(|id| (*id, 0usize))
+        .collect::<HashMap<_, _>>();
+    for (_, parents) in &commits {
+        for parent in parents {
+            if let Some(children) = waiting.get_mut(parent) {
+                *children += 1;
+            }
+        }
+    }
+
+    let parents = commits.into_iter().collect::<HashMap<_, _>>();
+    let mut ready = order
+        .iter()
+        .filter(|id| waiting[*id] == 0)
+        .copied()
+        .collect::<VecDeque<_>>();
+    let mut sorted = Vec::with_capacity(order.len());
+    while let Some(id) = ready.pop_front() {
+        sorted.push(id);
+        for parent in &parents[&id] {
+            let Some(children) = waiting.get_mut(parent) else {
+                continue;
+            };
+            *children -= 1;
+            if *children == 0 {
+                ready.push_back(*parent);
+            }
+        }
+    }
+    sorted
+}
+
+/// Identity of a record when comparing diffs of a merge against each parent.
+fn merge_key(record: &Record) -> (Option<String>, Operation, String) {
+    (
+        record.file_path.clone(),
+        record.operation,
+        record.content.clone(),
+    )
+}
+
+/// A file whose todos are being matched.
+struct ScannedFile<'a> {
+    path: Option<String>,
+    language: &'static Language,
+    config: &'a ScanConfig,
+}
+
+/// Todo changes found in one commit.
+#[derive(Debug, Default)]
+struct CommitChanges {
+    records: Vec<Record>,
+    /// Renamed files, as (from, to)
+    renames: Vec<(String, String)>,
+}
+
+impl CommitChanges {
+    /// Pair todos moved across hunks and files, then hand everything over to
+    /// the tracker.
+    fn emit(self, tracker: &LifecycleTracker) {
+        for (from, to) in self.renames {
+            tracker.rename(&from, &to);
+        }
+        for record in similar::pair_in_commit(self.records) {
+            tracker.record(record);
+        }
+        tracker.finish_commit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u8) -> ObjectId {
+        ObjectId::from([n; 20])
+    }
+
+    #[test]
+    fn children_come_before_parents_across_uneven_merges() {
+        // 1 - 2 ----------- 6
+        //  \               /
+        //   3 - 4 - 5 ----
+        let (root, main, merge) = (id(1), id(2), id(6));
+        let branch = [id(3), id(4), id(5)];
+        // breadth first from the merge reaches the root before the branch
+        let found = vec![
+            (merge, vec![main, branch[2]]),
+            (main, vec![root]),
+            (branch[2], vec![branch[1]]),
+            (root, vec![]),
+            (branch[1], vec![branch[0]]),
+            (branch[0], vec![root]),
+        ];
+        let parents = found.iter().cloned().collect::<HashMap<_, _>>();
+
+        let sorted = children_first(found);
+
+        assert_eq!(sorted.len(), 6);
+        let position = |id| sorted.iter().position(|sorted| *sorted == id).unwrap();
+        for (id, parents) in parents {
+            for parent in parents {
+                assert!(position(id) < position(parent));
+            }
+        }
+        assert_eq!(sorted.last(), Some(&root));
+    }
+
+    #[test]
+    fn parents_outside_the_walk_are_ignored() {
+        let found = vec![(id(3), vec![id(2)]), (id(2), vec![id(1)])];
+
+        assert_eq!(children_first(found), vec![id(3), id(2)]);
+    }
 }
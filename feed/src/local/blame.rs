@@ -0,0 +1,537 @@
+//! Find the commit that introduced a line.
+//!
+//! Starting from a commit, lines are followed back through the history,
+//! newest commit first. At each commit the file is diffed against every
+//! parent, under its old path if the parent doesn't have it and it is
+//! renamed. A line unchanged against a parent moves on to its line number
+//! there, and a line changed against all of them belongs to the commit. A
+//! file identical to one parent moves there as a whole, so lines merged from
+//! another branch are attributed to the commits on that branch.
+//!
+//! Parents missing from the repo, like beyond the boundary of a shallow
+//! clone, are left out. Lines that can't go further belong to the boundary
+//! commit.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::convert::Infallible;
+use std::ops::Range;
+
+use chrono::{DateTime, FixedOffset};
+use gix::bstr::BStr;
+use gix::object::tree::diff::change::Event;
+use gix::object::tree::diff::{Action, Rewrites};
+use gix::{Repository, Tree};
+use gix_hash::ObjectId;
+use gix_object::TreeRefIter;
+use serde::Serialize;
+use snafu::ResultExt;
+
+use super::identity::IdentityMap;
+use super::{line_diff, typed_time};
+use crate::error::{boxed, DiffTreeSnafu, FeedResult, ReadObjectSnafu};
+
+/// The commit that introduced a line.
+#[derive(Debug, Clone, Serialize)]
+pub struct Attribution {
+    pub commit_id: String,
+    /// Committer time, in the committer's UTC offset
+    pub commit_time: DateTime<FixedOffset>,
+    /// Canonical author, after `.mailmap` and the server's aliases
+    pub author_name: String,
+    pub author_email: String,
+    /// Author as committed
+    pub raw_author_name: String,
+    pub raw_author_email: String,
+}
+
+/// Lines of one file still waiting for their commit.
+struct PendingFile {
+    /// Blob of the file in the current commit
+    blob: ObjectId,
+    /// (index of target, 0-based line number in the current commit)
+    lines: Vec<(usize, u32)>,
+}
+
+/// A commit some lines are followed to, and the files they are in there.
+struct Visit {
+    info: CommitInfo,
+    files: HashMap<String, PendingFile>,
+}
+
+/// Attribute each `(file path, 0-based line number)` at commit `start`.
+/// The result is in the same order as `targets`.
+pub fn blame(
+    repo: &Repository,
+    identities: &IdentityMap,
+    start: ObjectId,
+    targets: &[(String, u32)],
+) -> FeedResult<Vec<Option<Attribution>>> {
+    let mut result = vec![None; targets.len()];
+
+    let start_tree = tree_of(repo, start)?.id;
+    let mut walk = Walk::new(repo, identities);
+    for (index, (path, line)) in targets.iter().enumerate() {
+        let Some(blob) = lookup(repo, start_tree, path)? else {
+            continue;
+        };
+        walk.follow(start, path.clone(), blob, vec![(index, *line)])?;
+    }
+
+    while let Some(Visit { info, files }) = walk.pop() {
+        let attribution = info.attribution;
+        let id = info.id;
+        let tree = tree_of(repo, id)?;
+        let parents = info
+            .parents
+            .iter()
+            .map(|parent| parent_tree(repo, *parent))
+            .collect::<FeedResult<Vec<_>>>()?;
+        // renames of each parent, as (new path, old path), found when needed
+        let mut renames: Vec<Option<HashMap<String, String>>> = vec![None; parents.len()];
+
+        for (path, file) in files {
+            // the file in each parent, under its old path if renamed
+            let mut sources = vec![];
+            for (index, parent_tree) in parents.iter().enumerate() {
+                let Some(parent_tree) = parent_tree else {
+                    sources.push(None);
+                    continue;
+                };
+                let source = match lookup(repo, parent_tree.id, &path)? {
+                    Some(blob) => Some((path.clone(), blob)),
+                    None => {
+                        if renames[index].is_none() {
+                            renames[index] = Some(find_renames(parent_tree, &tree, id)?);
+                        }
+                        let old_path = renames[index]
+                            .as_ref()
+                            .and_then(|renames| renames.get(&path));
+                        match old_path {
+                            Some(old_path) => lookup(repo, parent_tree.id, old_path)?
+                                .map(|blob| (old_path.clone(), blob)),
+                            None => None,
+                        }
+                    }
+                };
+                sources.push(source);
+            }
+
+            let same = sources.iter().position(|source| {
+                source
+                    .as_ref()
+                    .map_or(false, |(_, blob)| *blob == file.blob)
+            });
+            if let Some(index) = same {
+                let (source_path, blob) = sources.swap_remove(index).unwrap();
+                walk.follow(info.parents[index], source_path, blob, file.lines)?;
+                continue;
+            }
+
+            let mut lines = file.lines;
+            for (index, source) in sources.into_iter().enumerate() {
+                if lines.is_empty() {
+                    break;
+                }
+                let Some((source_path, blob)) = source else {
+                    continue;
+                };
+                let hunks = diff_hunks(repo, blob, file.blob)?;
+                let mut unchanged = vec![];
+                lines.retain(|(target, line)| match map_to_parent(&hunks, *line) {
+                    Some(parent_line) => {
+                        unchanged.push((*target, parent_line));
+                        false
+                    }
+                    None => true,
+                });
+                if !unchanged.is_empty() {
+                    walk.follow(info.parents[index], source_path, blob, unchanged)?;
+                }
+            }
+            // changed against every parent, or the file is created here, or
+            // the parents are missing
+            for (target, _) in lines {
+                result[target] = Some(attribution.clone());
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Commits lines are followed to, visited newest first so that a commit
+/// reached from several children takes the lines of all of them.
+struct Walk<'a> {
+    repo: &'a Repository,
+    identities: &'a IdentityMap,
+    visits: HashMap<ObjectId, Visit>,
+    queue: BinaryHeap<(DateTime<FixedOffset>, ObjectId)>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(repo: &'a Repository, identities: &'a IdentityMap) -> Self {
+        Self {
+            repo,
+            identities,
+            visits: HashMap::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Follow `lines` of the file at `path` to `commit`.
+    fn follow(
+        &mut self,
+        commit: ObjectId,
+        path: String,
+        blob: ObjectId,
+        lines: Vec<(usize, u32)>,
+    ) -> FeedResult<()> {
+        let visit = match self.visits.entry(commit) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let info = read_commit(self.repo, self.identities, commit)?;
+                self.queue.push((info.attribution.commit_time, commit));
+                entry.insert(Visit {
+                    info,
+                    files: HashMap::new(),
+                })
+            }
+        };
+        visit
+            .files
+            .entry(path)
+            .or_insert_with(|| PendingFile {
+                blob,
+                lines: vec![],
+            })
+            .lines
+            .extend(lines);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Visit> {
+        let (_, commit) = self.queue.pop()?;
+        self.visits.remove(&commit)
+    }
+}
+
+struct CommitInfo {
+    id: ObjectId,
+    attribution: Attribution,
+    parents: Vec<ObjectId>,
+}
+
+fn read_commit(
+    repo: &Repository,
+    identities: &IdentityMap,
+    id: ObjectId,
+) -> FeedResult<CommitInfo> {
+    let commit = super::read_commit(repo, id)?;
+    let author = commit.author().map_err(boxed).context(ReadObjectSnafu)?;
+    let commit_time = commit.time().map_err(boxed).context(ReadObjectSnafu)?;
+
+    let (raw_author, author) = identities.resolve(author);
+    let commit_id = id.to_string();
+
+    Ok(CommitInfo {
+        id,
+        attribution: Attribution {
+            commit_time: typed_time(&commit_id, commit_time)?,
+            commit_id,
+            author_name: author.name,
+            author_email: author.email,
+            raw_author_name: raw_author.name,
+            raw_author_email: raw_author.email,
+        },
+        parents: commit.parent_ids().map(|id| id.detach()).collect(),
+    })
+}
+
+fn tree_of(repo: &Repository, commit: ObjectId) -> FeedResult<Tree<'_>> {
+    let commit = super::read_commit(repo, commit)?;
+    commit.tree().map_err(boxed).context(ReadObjectSnafu)
+}
+
+/// Tree of a parent commit, `None` if the commit is missing from the repo.
+fn parent_tree(repo: &Repository, parent: ObjectId) -> FeedResult<Option<Tree<'_>>> {
+    let found = repo
+        .try_find_object(parent)
+        .map_err(boxed)
+        .context(ReadObjectSnafu)?;
+    match found {
+        Some(_) => tree_of(repo, parent).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Files renamed from the parent tree to the tree of `commit`, as
+/// (new path, old path).
+fn find_renames(
+    parent_tree: &Tree,
+    tree: &Tree,
+    commit: ObjectId,
+) -> FeedResult<HashMap<String, String>> {
+    let mut renames = HashMap::new();
+    parent_tree
+        .changes()
+        .map_err(boxed)
+        .with_context(|_| DiffTreeSnafu {
+            commit: commit.to_string(),
+        })?
+        .track_path()
+        .track_rewrites(Some(Rewrites::default()))
+        .for_each_to_obtain_tree(tree, |change| {
+            if let Event::Rewrite {
+                source_location,
+                copy: false,
+                ..
+            } = &change.event
+            {
+                renames.insert(change.location.to_string(), source_location.to_string());
+            }
+            Ok::<_, Infallible>(Action::Continue)
+        })
+        .map_err(boxed)
+        .with_context(|_| DiffTreeSnafu {
+            commit: commit.to_string(),
+        })?;
+    Ok(renames)
+}
+
+/// Find the blob at `path` in the given tree.
+fn lookup(repo: &Repository, tree: ObjectId, path: &str) -> FeedResult<Option<ObjectId>> {
+    let mut curr = tree;
+    let mut components = path.split('/').peekable();
+    while let Some(component) = components.next() {
+        let object = repo
+            .find_object(curr)
+            .map_err(boxed)
+            .context(ReadObjectSnafu)?;
+        let entry = TreeRefIter::from_bytes(&object.data)
+            .filter_map(Result::ok)
+            .find(|entry| entry.filename == BStr::new(component));
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+
+        let is_last = components.peek().is_none();
+        if is_last != entry.mode.is_no_tree() {
+            return Ok(None);
+        }
+        curr = entry.oid.to_owned();
+    }
+
+    Ok(Some(curr))
+}
+
+/// Changed hunks between two blobs, as (range in old, range in new).
+fn diff_hunks(
+    repo: &Repository,
+    old: ObjectId,
+    new: ObjectId,
+) -> FeedResult<Vec<(Range<u32>, Range<u32>)>> {
+    let old = repo
+        .find_object(old)
+        .map_err(boxed)
+        .context(ReadObjectSnafu)?;
+    let new = repo
+        .find_object(new)
+        .map_err(boxed)
+        .context(ReadObjectSnafu)?;
+
+    Ok(line_diff::hunks(&old.data, &new.data))
+}
+
+/// Line number of an unchanged line in the old blob. `None` if the line is
+/// changed.
+fn map_to_parent(hunks: &[(Range<u32>, Range<u32>)], line: u32) -> Option<u32> {
+    let mut offset: i64 = 0;
+    for (before, after) in hunks {
+        if after.contains(&line) {
+            return None;
+        }
+        if after.start > line {
+            break;
+        }
+        offset += before.len() as i64 - after.len() as i64;
+    }
+    Some((line as i64 + offset) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A repository built with the `git` command line.
+    struct TestRepo {
+        dir: TempDir,
+        commits: u32,
+    }
+
+    impl TestRepo {
+        fn new() -> Self {
+            let repo = Self {
+                dir: TempDir::new().unwrap(),
+                commits: 0,
+            };
+            repo.git(&["init", "-q", "-b", "main"]);
+            repo
+        }
+
+        fn git(&self, args: &[&str]) -> String {
+            git(self.dir.path(), args, self.commits)
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            std::fs::write(self.dir.path().join(path), content).unwrap();
+        }
+
+        /// Commit everything, one minute after the previous commit.
+        fn commit(&mut self, message: &str) -> ObjectId {
+            self.commits += 1;
+            self.git(&["add", "-A"]);
+            self.git(&["commit", "-q", "-m", message]);
+            self.head()
+        }
+
+        fn merge(&mut self, branch: &str) -> ObjectId {
+            self.commits += 1;
+            self.git(&["merge", "-q", "--no-edit", branch]);
+            self.head()
+        }
+
+        fn head(&self) -> ObjectId {
+            ObjectId::from_hex(self.git(&["rev-parse", "HEAD"]).as_bytes()).unwrap()
+        }
+    }
+
+    fn git(dir: &Path, args: &[&str], minutes: u32) -> String {
+        let date = format!("{} +0000", 1_700_000_000 + minutes * 60);
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "Author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "Author")
+            .env("GIT_COMMITTER_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// Commit ids blamed for `lines` of `path` at `start`.
+    fn blame_lines(dir: &Path, start: ObjectId, path: &str, lines: &[u32]) -> Vec<ObjectId> {
+        let repo = gix::open(dir).unwrap();
+        let identities = IdentityMap::new(&repo, None);
+        let targets = lines
+            .iter()
+            .map(|line| (path.to_string(), *line))
+            .collect::<Vec<_>>();
+        blame(&repo, &identities, start, &targets)
+            .unwrap()
+            .into_iter()
+            .map(|attribution| {
+                ObjectId::from_hex(attribution.unwrap().commit_id.as_bytes()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lines_map_past_changed_hunks() {
+        // line 0 is replaced by two lines, and two are inserted after line 5
+        let hunks = vec![(0..1, 0..2), (5..5, 6..8)];
+
+        assert_eq!(map_to_parent(&hunks, 0), None);
+        assert_eq!(map_to_parent(&hunks, 1), None);
+        assert_eq!(map_to_parent(&hunks, 2), Some(1));
+        assert_eq!(map_to_parent(&hunks, 5), Some(4));
+        assert_eq!(map_to_parent(&hunks, 6), None);
+        assert_eq!(map_to_parent(&hunks, 7), None);
+        assert_eq!(map_to_parent(&hunks, 8), Some(5));
+        assert_eq!(map_to_parent(&[], 3), Some(3));
+    }
+
+    #[test]
+    fn lines_are_blamed_on_the_commit_that_changed_them() {
+        let mut repo = TestRepo::new();
+        repo.write("a.rs", "a\nb\nc\n");
+        let first = repo.commit("first");
+        repo.write("a.rs", "a\nB\nc\nd\n");
+        let second = repo.commit("second");
+
+        let blamed = blame_lines(repo.dir.path(), second, "a.rs", &[0, 1, 2, 3]);
+
+        assert_eq!(blamed, vec![first, second, first, second]);
+    }
+
+    #[test]
+    fn merged_lines_are_blamed_on_their_branch() {
+        let mut repo = TestRepo::new();
+        repo.write("a.rs", "x\n1\n2\n3\n4\ny\n");
+        let base = repo.commit("base");
+        repo.git(&["checkout", "-q", "-b", "side"]);
+        repo.write("a.rs", "x\n1\n2\n3\n4\ny\nside\n");
+        let side = repo.commit("side");
+        repo.git(&["checkout", "-q", "main"]);
+        repo.write("a.rs", "main\nx\n1\n2\n3\n4\ny\n");
+        let main = repo.commit("main");
+        let merge = repo.merge("side");
+
+        let blamed = blame_lines(repo.dir.path(), merge, "a.rs", &[0, 1, 6, 7]);
+
+        assert_eq!(blamed, vec![main, base, base, side]);
+    }
+
+    #[test]
+    fn lines_are_followed_through_renames() {
+        let mut repo = TestRepo::new();
+        repo.write("old.rs", "a\nb\nc\nd\ne\n");
+        let first = repo.commit("first");
+        repo.git(&["mv", "old.rs", "new.rs"]);
+        repo.commit("rename");
+        repo.write("new.rs", "a\nb\nc\nd\ne\nf\n");
+        let last = repo.commit("append");
+
+        let blamed = blame_lines(repo.dir.path(), last, "new.rs", &[0, 4, 5]);
+
+        assert_eq!(blamed, vec![first, first, last]);
+    }
+
+    #[test]
+    fn shallow_clones_stop_at_the_boundary() {
+        let mut repo = TestRepo::new();
+        repo.write("a.rs", "a\n");
+        repo.commit("first");
+        repo.write("a.rs", "a\nb\n");
+        let last = repo.commit("second");
+
+        let clone = TempDir::new().unwrap();
+        let source = format!("file://{}", repo.dir.path().display());
+        let target = clone.path().join("shallow");
+        git(
+            clone.path(),
+            &[
+                "clone",
+                "-q",
+                "--depth",
+                "1",
+                &source,
+                target.to_str().unwrap(),
+            ],
+            0,
+        );
+
+        let blamed = blame_lines(&target, last, "a.rs", &[0, 1]);
+
+        assert_eq!(blamed, vec![last, last]);
+    }
+}
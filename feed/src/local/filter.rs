@@ -0,0 +1,282 @@
+//! Decide which files are scanned.
+//!
+//! Vendored, generated and binary files are skipped. They are found by the
+//! linguist attributes in `.gitattributes` at the scanned revision, and by a
+//! built-in list of common vendored paths. Requests can narrow this further
+//! with include and exclude globs.
+
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use snafu::ResultExt;
+
+use crate::error::{FeedResult, InvalidGlobSnafu};
+
+/// Paths taken as vendored unless `.gitattributes` says otherwise.
+const VENDORED: &[&str] = &[
+    "**/vendor/**",
+    "**/node_modules/**",
+    "**/third_party/**",
+    "**/*.min.js",
+    "**/*.min.css",
+    "**/*.lock",
+    "**/package-lock.json",
+    "**/pnpm-lock.yaml",
+    "**/go.sum",
+];
+
+/// Number of leading bytes looked at to tell whether a blob is binary, the
+/// same as git does.
+const BINARY_PROBE_LEN: usize = 8000;
+
+#[derive(Debug)]
+pub struct PathFilter {
+    /// Only paths matching these are scanned, if given
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    vendored: GlobSet,
+    /// Rules from `.gitattributes`, in file order
+    attributes: Vec<AttributeRule>,
+    max_blob_size: usize,
+}
+
+/// One line of `.gitattributes`, with only the attributes we care about.
+/// `None` leaves the attribute as set by earlier lines.
+#[derive(Debug)]
+struct AttributeRule {
+    glob: GlobMatcher,
+    vendored: Option<bool>,
+    generated: Option<bool>,
+    binary: Option<bool>,
+}
+
+impl PathFilter {
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        max_blob_size: usize,
+        gitattributes: Option<&[u8]>,
+    ) -> FeedResult<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(glob_set(include.iter().map(String::as_str))?)
+        };
+
+        Ok(Self {
+            include,
+            exclude: glob_set(exclude.iter().map(String::as_str))?,
+            vendored: glob_set(VENDORED.iter().copied())?,
+            attributes: gitattributes.map(parse_gitattributes).unwrap_or_default(),
+            max_blob_size,
+        })
+    }
+
+    /// Whether the file at `path` is scanned at all.
+    pub fn is_scanned(&self, path: &str) -> bool {
+        if let Some(include) = &self.include {
+            if !include.is_match(path) {
+                return false;
+            }
+        }
+        if self.exclude.is_match(path) {
+            return false;
+        }
+
+        let (mut vendored, mut generated, mut binary) = (None, None, None);
+        // later lines take precedence
+        for rule in self.attributes.iter().filter(|r| r.glob.is_match(path)) {
+            vendored = rule.vendored.or(vendored);
+            generated = rule.generated.or(generated);
+            binary = rule.binary.or(binary);
+        }
+        if generated == Some(true) || binary == Some(true) {
+            return false;
+        }
+        !vendored.unwrap_or_else(|| self.vendored.is_match(path))
+    }
+
+    /// Whether the content of a blob is scanned. Blobs over the size ceiling
+    /// and binary blobs are not.
+    pub fn is_scanned_blob(&self, data: &[u8]) -> bool {
+        let probe = &data[..data.len().min(BINARY_PROBE_LEN)];
+        data.len() <= self.max_blob_size && !probe.contains(&0)
+    }
+}
+
+fn glob_set<'a>(globs: impl Iterator<Item = &'a str>) -> FeedResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).context(InvalidGlobSnafu)?);
+    }
+    builder.build().context(InvalidGlobSnafu)
+}
+
+/// Lines with a pattern we can't parse are skipped.
+fn parse_gitattributes(data: &[u8]) -> Vec<AttributeRule> {
+    let mut rules = vec![];
+    for line in String::from_utf8_lossy(data).lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next() else {
+            continue;
+        };
+        let Ok(glob) = GlobBuilder::new(&attribute_glob(pattern))
+            .literal_separator(true)
+            .build()
+        else {
+            continue;
+        };
+
+        let mut rule = AttributeRule {
+            glob: glob.compile_matcher(),
+            vendored: None,
+            generated: None,
+            binary: None,
+        };
+        for (name, value) in fields.filter_map(parse_attribute) {
+            match name {
+                "linguist-vendored" => rule.vendored = Some(value),
+                "linguist-generated" => rule.generated = Some(value),
+                "binary" => rule.binary = Some(value),
+                // `-text` and `-diff` are what the `binary` macro expands to
+                "text" | "diff" => rule.binary = Some(!value),
+                _ => {}
+            }
+        }
+        rules.push(rule);
+    }
+    rules
+}
+
+/// Pattern in `.gitattributes` to a glob relative to the repo root. Patterns
+/// without a slash match at any depth.
+fn attribute_glob(pattern: &str) -> String {
+    if let Some(anchored) = pattern.strip_prefix('/') {
+        anchored.to_string()
+    } else if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{pattern}")
+    }
+}
+
+/// `name`, `-name` and `name=value` to the attribute name and whether it is
+/// set. `!name` unsets an attribute, which we don't track.
+fn parse_attribute(attribute: &str) -> Option<(&str, bool)> {
+    if attribute.starts_with('!') {
+        return None;
+    }
+    if let Some(name) = attribute.strip_prefix('-') {
+        return Some((name, false));
+    }
+    match attribute.split_once('=') {
+        Some((name, value)) => Some((name, value != "false")),
+        None => Some((attribute, true)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_filter(include: &[&str], exclude: &[&str], gitattributes: &str) -> PathFilter {
+        let globs = |globs: &[&str]| globs.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        PathFilter::new(
+            &globs(include),
+            &globs(exclude),
+            16,
+            Some(gitattributes.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn later_attribute_lines_take_precedence() {
+        let filter = path_filter(
+            &[],
+            &[],
+            "*.rs linguist-generated\nsrc/keep.rs -linguist-generated\n",
+        );
+
+        assert!(!filter.is_scanned("src/gen.rs"));
+        assert!(filter.is_scanned("src/keep.rs"));
+
+        let filter = path_filter(
+            &[],
+            &[],
+            "src/keep.rs -linguist-generated\n*.rs linguist-generated\n",
+        );
+        assert!(!filter.is_scanned("src/keep.rs"));
+    }
+
+    #[test]
+    fn binary_attributes_skip_files() {
+        let filter = path_filter(
+            &[],
+            &[],
+            "*.a -text\n*.b -diff\n*.c binary\n*.d linguist-generated=true\n\
+             *.e linguist-generated=false\n*.f text\n",
+        );
+
+        assert!(!filter.is_scanned("x.a"));
+        assert!(!filter.is_scanned("x.b"));
+        assert!(!filter.is_scanned("x.c"));
+        assert!(!filter.is_scanned("x.d"));
+        assert!(filter.is_scanned("x.e"));
+        assert!(filter.is_scanned("x.f"));
+    }
+
+    #[test]
+    fn vendored_paths_follow_attributes() {
+        let filter = path_filter(
+            &[],
+            &[],
+            "vendor/ours/** -linguist-vendored\nlib/** linguist-vendored\n",
+        );
+
+        assert!(!filter.is_scanned("vendor/theirs/lib.rs"));
+        assert!(filter.is_scanned("vendor/ours/lib.rs"));
+        assert!(!filter.is_scanned("lib/lib.rs"));
+        assert!(filter.is_scanned("src/lib.rs"));
+    }
+
+    #[test]
+    fn anchored_patterns_only_match_at_the_root() {
+        let filter = path_filter(
+            &[],
+            &[],
+            "/gen.rs linguist-generated\nout.rs linguist-generated\ndocs/*.md binary\n",
+        );
+
+        assert!(!filter.is_scanned("gen.rs"));
+        assert!(filter.is_scanned("src/gen.rs"));
+        assert!(!filter.is_scanned("out.rs"));
+        assert!(!filter.is_scanned("src/deep/out.rs"));
+        assert!(!filter.is_scanned("docs/a.md"));
+        assert!(filter.is_scanned("docs/sub/a.md"));
+        assert!(filter.is_scanned("src/docs/a.md"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = path_filter(&["src/**"], &["src/generated/**"], "");
+
+        assert!(filter.is_scanned("src/lib.rs"));
+        assert!(!filter.is_scanned("src/generated/api.rs"));
+        assert!(!filter.is_scanned("tests/lib.rs"));
+        // include doesn't bring back vendored paths
+        assert!(!filter.is_scanned("src/vendor/lib.rs"));
+    }
+
+    #[test]
+    fn blobs_over_the_ceiling_or_binary_are_skipped() {
+        let filter = path_filter(&[], &[], "");
+
+        assert!(filter.is_scanned_blob(b"// TODO: fit"));
+        assert!(filter.is_scanned_blob(&[b'a'; 16]));
+        assert!(!filter.is_scanned_blob(&[b'a'; 17]));
+        assert!(!filter.is_scanned_blob(b"bin\0ary"));
+    }
+}
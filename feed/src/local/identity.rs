@@ -0,0 +1,50 @@
+//! Map the names and emails one person commits with to a single identity.
+//!
+//! The repository's `.mailmap` is applied, merged with the server's alias
+//! table. The alias table uses the `.mailmap` format and wins on conflicts.
+
+use std::fmt::Debug;
+
+use gix::actor::SignatureRef;
+use gix::mailmap::Snapshot;
+use gix::Repository;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+pub struct IdentityMap {
+    mailmap: Snapshot,
+}
+
+impl IdentityMap {
+    pub fn new(repo: &Repository, aliases: Option<&[u8]>) -> Self {
+        let mut mailmap = repo.open_mailmap();
+        if let Some(aliases) = aliases {
+            mailmap.merge(gix::mailmap::parse_ignore_errors(aliases));
+        }
+        Self { mailmap }
+    }
+
+    /// The identity as committed and its canonical form.
+    pub fn resolve(&self, signature: SignatureRef) -> (Identity, Identity) {
+        let raw = Identity {
+            name: signature.name.to_string(),
+            email: signature.email.to_string(),
+        };
+        let canonical = self.mailmap.resolve(signature);
+        let canonical = Identity {
+            name: canonical.name.to_string(),
+            email: canonical.email.to_string(),
+        };
+        (raw, canonical)
+    }
+}
+
+impl Debug for IdentityMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentityMap").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,451 @@
+//! Comment syntax of the languages we know about.
+//!
+//! Diffs come line by line, so the lexer here only looks at one line at a
+//! time. It skips string literals and returns the text inside comments,
+//! which is what the todo patterns are matched against.
+
+#[derive(Debug)]
+pub struct Language {
+    pub name: &'static str,
+    /// File extensions without the leading dot
+    extensions: &'static [&'static str],
+    /// Whole file names, for files like `Makefile` that have no extension
+    file_names: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+    /// Bytes that open and close a string literal
+    quotes: &'static [u8],
+}
+
+const C_LINE: &[&str] = &["//"];
+const C_BLOCK: &[(&str, &str)] = &[("/*", "*/")];
+const HASH_LINE: &[&str] = &["#"];
+const PERCENT_LINE: &[&str] = &["%"];
+const XML_BLOCK: &[(&str, &str)] = &[("<!--", "-->")];
+
+static LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        extensions: &["rs"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        // `'` is left out as it also starts a lifetime
+        quotes: b"\"",
+    },
+    Language {
+        name: "c",
+        extensions: &["c", "h"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "cpp",
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "go",
+        extensions: &["go"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'`",
+    },
+    Language {
+        name: "java",
+        extensions: &["java"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "kotlin",
+        extensions: &["kt", "kts"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "scala",
+        extensions: &["scala"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "groovy",
+        extensions: &["groovy"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "javascript",
+        extensions: &["js", "jsx", "mjs", "cjs", "ts", "tsx"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'`",
+    },
+    Language {
+        name: "csharp",
+        extensions: &["cs"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "swift",
+        extensions: &["swift"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "dart",
+        extensions: &["dart"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "zig",
+        extensions: &["zig"],
+        file_names: &[],
+        // zig has line comments only
+        line_comments: C_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "protobuf",
+        extensions: &["proto"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "css",
+        extensions: &["css"],
+        file_names: &[],
+        line_comments: &[],
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "scss",
+        extensions: &["scss", "less"],
+        file_names: &[],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        quotes: b"\"'",
+    },
+    Language {
+        name: "python",
+        extensions: &["py", "pyi"],
+        file_names: &[],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "shell",
+        extensions: &["sh", "bash", "zsh", "fish"],
+        file_names: &[],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "ruby",
+        extensions: &["rb"],
+        file_names: &["Gemfile", "Rakefile"],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "perl",
+        extensions: &["pl", "pm"],
+        file_names: &[],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "r",
+        extensions: &["r"],
+        file_names: &[],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "config",
+        extensions: &["toml", "yaml", "yml", "cmake", "nix", "tf"],
+        file_names: &["CMakeLists.txt", "Dockerfile", ".gitignore"],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "makefile",
+        extensions: &["mk"],
+        file_names: &["Makefile", "makefile", "GNUmakefile"],
+        line_comments: HASH_LINE,
+        block_comments: &[],
+        quotes: b"",
+    },
+    Language {
+        name: "sql",
+        extensions: &["sql"],
+        file_names: &[],
+        line_comments: &["--"],
+        block_comments: C_BLOCK,
+        quotes: b"'",
+    },
+    Language {
+        name: "lua",
+        extensions: &["lua"],
+        file_names: &[],
+        line_comments: &["--"],
+        block_comments: &[("--[[", "]]")],
+        quotes: b"\"'",
+    },
+    Language {
+        name: "haskell",
+        extensions: &["hs", "elm"],
+        file_names: &[],
+        line_comments: &["--"],
+        block_comments: &[("{-", "-}")],
+        quotes: b"\"",
+    },
+    Language {
+        name: "html",
+        extensions: &["html", "htm", "xml", "svg", "vue", "md", "markdown"],
+        file_names: &[],
+        line_comments: &[],
+        block_comments: XML_BLOCK,
+        quotes: b"",
+    },
+    Language {
+        name: "lisp",
+        extensions: &["lisp", "el", "clj", "cljs", "scm"],
+        file_names: &[],
+        line_comments: &[";"],
+        block_comments: &[],
+        quotes: b"\"",
+    },
+    Language {
+        name: "erlang",
+        extensions: &["erl", "hrl"],
+        file_names: &[],
+        line_comments: PERCENT_LINE,
+        block_comments: &[],
+        quotes: b"\"",
+    },
+    Language {
+        name: "tex",
+        extensions: &["tex"],
+        file_names: &[],
+        line_comments: PERCENT_LINE,
+        block_comments: &[],
+        quotes: b"",
+    },
+];
+
+impl Language {
+    /// Find the language of a file by its path. Returns `None` for files we
+    /// don't know the comment syntax of.
+    pub fn detect(path: &str) -> Option<&'static Language> {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        if let Some(lang) = LANGUAGES.iter().find(|l| l.file_names.contains(&file_name)) {
+            return Some(lang);
+        }
+
+        let (_, extension) = file_name.rsplit_once('.')?;
+        let extension = extension.to_ascii_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|l| l.extensions.contains(&extension.as_str()))
+    }
+
+    /// Text of every comment in this line, with the comment delimiters
+    /// stripped.
+    ///
+    /// A line that starts with `* ` in a language with `/* */` comments is
+    /// taken as the continuation of a block comment.
+    pub fn comments<'a>(&self, line: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut comments = vec![];
+
+        let trimmed = line.trim_ascii_start();
+        if self.block_comments.contains(&("/*", "*/"))
+            && (trimmed == b"*" || trimmed.starts_with(b"* "))
+        {
+            comments.push(&trimmed[1..]);
+            return comments;
+        }
+
+        let mut quote: Option<u8> = None;
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+
+            if let Some(q) = quote {
+                if rest[0] == b'\\' {
+                    i += 2;
+                    continue;
+                }
+                if rest[0] == q {
+                    quote = None;
+                }
+                i += 1;
+                continue;
+            }
+
+            // block comments are checked first as some of them begin with a
+            // line comment token, like `--[[` in lua
+            if let Some((start, end)) = self
+                .block_comments
+                .iter()
+                .find(|(start, _)| rest.starts_with(start.as_bytes()))
+            {
+                let body = &rest[start.len()..];
+                match find(body, end.as_bytes()) {
+                    Some(pos) => {
+                        comments.push(&body[..pos]);
+                        i += start.len() + pos + end.len();
+                        continue;
+                    }
+                    None => {
+                        comments.push(body);
+                        break;
+                    }
+                }
+            }
+
+            if let Some(token) = self
+                .line_comments
+                .iter()
+                .find(|token| rest.starts_with(token.as_bytes()))
+            {
+                comments.push(&rest[token.len()..]);
+                break;
+            }
+
+            if self.quotes.contains(&rest[0]) {
+                quote = Some(rest[0]);
+            }
+            i += 1;
+        }
+
+        comments
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(path: &str, line: &str) -> Vec<String> {
+        Language::detect(path)
+            .unwrap()
+            .comments(line.as_bytes())
+            .into_iter()
+            .map(|comment| String::from_utf8_lossy(comment).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn detect_by_extension_and_file_name() {
+        let name = |path| Language::detect(path).map(|language| language.name);
+        assert_eq!(name("src/main.rs"), Some("rust"));
+        assert_eq!(name("app/Main.KT"), Some("kotlin"));
+        assert_eq!(name("build.gradle.kts"), Some("kotlin"));
+        assert_eq!(name("lib/a.dart"), Some("dart"));
+        assert_eq!(name("src/main.zig"), Some("zig"));
+        assert_eq!(name("api/service.proto"), Some("protobuf"));
+        assert_eq!(name("paper/main.tex"), Some("tex"));
+        assert_eq!(name("script/run.pl"), Some("perl"));
+        assert_eq!(name("analysis/plot.R"), Some("r"));
+        assert_eq!(name("docker/Dockerfile"), Some("config"));
+        assert_eq!(name("README"), None);
+        assert_eq!(name("data.bin"), None);
+    }
+
+    #[test]
+    fn line_comment() {
+        assert_eq!(comments("a.rs", "let x = 1; // TODO: fix"), [" TODO: fix"]);
+        assert_eq!(comments("a.py", "x = 1  # TODO"), [" TODO"]);
+        assert_eq!(comments("a.rs", "let x = 1;"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn comment_tokens_in_string_literals_are_skipped() {
+        assert_eq!(
+            comments("a.rs", r#"let url = "http://example.com"; // TODO"#),
+            [" TODO"]
+        );
+        assert_eq!(
+            comments("a.py", "s = '# not a comment'"),
+            Vec::<String>::new()
+        );
+        // escaped quotes don't end the literal
+        assert_eq!(
+            comments("a.c", r#"puts("\" // still a string"); // real"#),
+            [" real"]
+        );
+        assert_eq!(comments("a.go", "s := `/* raw */` /* TODO */"), [" TODO "]);
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(
+            comments("a.c", "int x; /* TODO: a */ int y; /* b */"),
+            [" TODO: a ", " b "]
+        );
+        // unterminated, continued on the next lines
+        assert_eq!(comments("a.c", "/* TODO: long"), [" TODO: long"]);
+        assert_eq!(comments("a.c", "   * TODO: more"), [" TODO: more"]);
+        assert_eq!(comments("a.lua", "--[[ TODO ]] x = 1"), [" TODO "]);
+        assert_eq!(comments("a.lua", "x = 1 -- TODO"), [" TODO"]);
+    }
+
+    #[test]
+    fn xml_comments() {
+        assert_eq!(
+            comments("index.html", "<p>text</p> <!-- TODO: style -->"),
+            [" TODO: style "]
+        );
+        assert_eq!(
+            comments("README.md", "<!-- TODO: one --> and <!-- two -->"),
+            [" TODO: one ", " two "]
+        );
+        assert_eq!(comments("README.md", "# TODO list"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn zig_has_no_block_comments() {
+        assert_eq!(comments("a.zig", "const a = b /* c; // TODO"), [" TODO"]);
+    }
+}
@@ -0,0 +1,592 @@
+//! Pair the add and remove records of one todo into a [Lifecycle].
+//!
+//! History is walked from new to old, so the remove of a todo shows up
+//! before the add that introduced it. Removes are kept pending until an add
+//! with the same file path and normalized content is found in an older
+//! commit. Renames seen during the walk move pending removes back to the old
+//! path once the renaming commit is done, and modified or moved todos
+//! continue as their previous content and path.
+//!
+//! Todos an earlier walk stored as open are handed in, by where that walk
+//! left them. Each lifecycle carries where its todo is when resolved, or at
+//! the walked revision if it is alive, so a todo edited, moved or renamed
+//! after its add is still found. A todo followed back to a stored one closes
+//! or updates it, and so does a lifecycle completed again by walking the
+//! whole history.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use super::similar::normalize;
+use crate::consumer::Consumer;
+use crate::schema::{Lifecycle, LifecycleEvent, OpenLifecycle, Operation, Record};
+
+/// (file path, normalized content)
+type TodoKey = (String, String);
+
+/// A todo followed back from where the walk found it.
+#[derive(Debug)]
+struct Followed {
+    /// Where the todo is when resolved, or at the walked revision
+    latest: TodoKey,
+    /// `None` if the todo is alive at the walked revision
+    resolved: Option<LifecycleEvent>,
+}
+
+pub struct LifecycleTracker<'a> {
+    inner: &'a dyn Consumer,
+    repo_name: String,
+    ref_name: String,
+    /// Todos waiting for their add, by where they are in the commit being
+    /// walked. The last one is the oldest.
+    pending: RefCell<HashMap<TodoKey, Vec<Followed>>>,
+    /// Removes of the current commit. They can only pair with adds of older
+    /// commits.
+    staged: RefCell<Vec<(TodoKey, Followed)>>,
+    /// Modified or moved todos of the current commit, as (previous, current)
+    continued: RefCell<Vec<(TodoKey, TodoKey)>>,
+    /// Renames of the current commit, as (from, to)
+    renames: RefCell<Vec<(String, String)>>,
+    /// Todos stored as open and not met by this walk yet, by their latest
+    /// path and content
+    stored_open: RefCell<HashMap<TodoKey, Vec<OpenLifecycle>>>,
+}
+
+impl<'a> LifecycleTracker<'a> {
+    pub fn new(
+        inner: &'a dyn Consumer,
+        repo_name: String,
+        ref_name: String,
+        stored_open: Vec<OpenLifecycle>,
+    ) -> Self {
+        let mut stored = HashMap::<TodoKey, Vec<OpenLifecycle>>::new();
+        for open in stored_open {
+            stored
+                .entry((open.latest_file_path.clone(), open.latest_content.clone()))
+                .or_default()
+                .push(open);
+        }
+        Self {
+            inner,
+            repo_name,
+            ref_name,
+            pending: RefCell::new(HashMap::new()),
+            staged: RefCell::new(Vec::new()),
+            continued: RefCell::new(Vec::new()),
+            renames: RefCell::new(Vec::new()),
+            stored_open: RefCell::new(stored),
+        }
+    }
+
+    /// File `from` is renamed to `to` in the commit being walked.
+    pub fn rename(&self, from: &str, to: &str) {
+        self.renames
+            .borrow_mut()
+            .push((from.to_string(), to.to_string()));
+    }
+
+    /// All changes of the current commit are observed. Todos removed under
+    /// a renamed path were added under the old one, so move them over.
+    pub fn finish_commit(&self) {
+        let mut pending = self.pending.borrow_mut();
+        for (key, followed) in self.staged.borrow_mut().drain(..) {
+            pending.entry(key).or_default().push(followed);
+        }
+        for (previous, current) in self.continued.borrow_mut().drain(..) {
+            // nothing pending means it is alive at the walked revision
+            let followed = pending.remove(&current).unwrap_or_else(|| {
+                vec![Followed {
+                    latest: current,
+                    resolved: None,
+                }]
+            });
+            pending.entry(previous).or_default().extend(followed);
+        }
+        for (from, to) in self.renames.borrow_mut().drain(..) {
+            let keys = pending
+                .keys()
+                .filter(|(path, _)| *path == to)
+                .cloned()
+                .collect::<Vec<_>>();
+            for key in keys {
+                let followed = pending.remove(&key).unwrap();
+                pending
+                    .entry((from.clone(), key.1))
+                    .or_default()
+                    .extend(followed);
+            }
+        }
+    }
+
+    /// Emit lifecycles of todos whose add is not in the walked range. The
+    /// oldest of them continue the todos stored as open where the earlier
+    /// walk left them.
+    pub fn finish(self) {
+        let pending = std::mem::take(&mut *self.pending.borrow_mut());
+        let mut stored_open = std::mem::take(&mut *self.stored_open.borrow_mut());
+        for (key, followed) in pending {
+            let mut stored = stored_open.remove(&key).unwrap_or_default();
+            for Followed { latest, resolved } in followed.into_iter().rev() {
+                match stored.pop() {
+                    Some(open) => self.emit(
+                        (open.file_path, open.content),
+                        Some(open.introduced),
+                        resolved,
+                        latest,
+                        true,
+                    ),
+                    // alive since before the walked range, nothing to tell
+                    None if resolved.is_none() => {}
+                    None => self.emit(key.clone(), None, resolved, latest, false),
+                }
+            }
+        }
+    }
+
+    /// Take the stored open todo added as `key` by `commit_id`, if there is
+    /// one.
+    fn take_stored(&self, key: &TodoKey, commit_id: &str) -> Option<OpenLifecycle> {
+        let mut stored_open = self.stored_open.borrow_mut();
+        stored_open.values_mut().find_map(|opens| {
+            let index = opens.iter().position(|open| {
+                open.introduced.commit_id == commit_id
+                    && open.file_path == key.0
+                    && open.content == key.1
+            })?;
+            Some(opens.swap_remove(index))
+        })
+    }
+
+    fn emit(
+        &self,
+        (file_path, content): TodoKey,
+        introduced: Option<LifecycleEvent>,
+        resolved: Option<LifecycleEvent>,
+        (latest_file_path, latest_content): TodoKey,
+        closes_stored: bool,
+    ) {
+        let age_secs = introduced
+            .as_ref()
+            .zip(resolved.as_ref())
+            .map(|(introduced, resolved)| {
+                (resolved.commit_time - introduced.commit_time).num_seconds()
+            });
+        self.inner.lifecycle(Lifecycle {
+            repo_name: self.repo_name.clone(),
+            ref_name: self.ref_name.clone(),
+            file_path,
+            content,
+            introduced,
+            resolved,
+            age_secs,
+            latest_file_path,
+            latest_content,
+            closes_stored,
+        });
+    }
+
+    fn observe(&self, record: &Record) {
+        let Some(file_path) = &record.file_path else {
+            return;
+        };
+        let key = (file_path.clone(), normalize(&record.content));
+
+        match record.operation {
+            Operation::Remove => {
+                let followed = Followed {
+                    latest: key.clone(),
+                    resolved: Some(record.into()),
+                };
+                self.staged.borrow_mut().push((key, followed));
+            }
+            Operation::Modify | Operation::Move => {
+                let previous_path = record
+                    .previous_file_path
+                    .clone()
+                    .unwrap_or_else(|| key.0.clone());
+                let previous_content = record
+                    .previous_content
+                    .as_deref()
+                    .map(normalize)
+                    .unwrap_or_else(|| key.1.clone());
+                self.continued
+                    .borrow_mut()
+                    .push(((previous_path, previous_content), key));
+            }
+            Operation::Add => {
+                let followed = self
+                    .pending
+                    .borrow_mut()
+                    .get_mut(&key)
+                    .and_then(|followed| followed.pop());
+                let Followed { latest, resolved } = followed.unwrap_or_else(|| Followed {
+                    latest: key.clone(),
+                    resolved: None,
+                });
+                let introduced = LifecycleEvent::from(record);
+                // stored by a walk that didn't see where it ends up yet
+                let closes_stored =
+                    self.take_stored(&key, &introduced.commit_id)
+                        .map_or(false, |open| {
+                            resolved.is_some()
+                                || (open.latest_file_path, open.latest_content) != latest
+                        });
+                self.emit(key, Some(introduced), resolved, latest, closes_stored);
+            }
+        }
+    }
+}
+
+impl Consumer for LifecycleTracker<'_> {
+    fn record(&self, record: Record) {
+        self.observe(&record);
+        self.inner.record(record);
+    }
+
+    fn lifecycle(&self, lifecycle: Lifecycle) {
+        self.inner.lifecycle(lifecycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::*;
+    use crate::local::{Identity, Todo};
+    use crate::schema::{CommitTimes, RecordBuilder};
+
+    #[derive(Default)]
+    struct Collect {
+        lifecycles: RefCell<Vec<Lifecycle>>,
+    }
+
+    impl Consumer for Collect {
+        fn record(&self, _record: Record) {}
+
+        fn lifecycle(&self, lifecycle: Lifecycle) {
+            self.lifecycles.borrow_mut().push(lifecycle);
+        }
+    }
+
+    /// A change of commit `c<n>`, committed at `n` hours.
+    struct Change {
+        operation: Operation,
+        path: &'static str,
+        content: &'static str,
+        previous: Option<(&'static str, &'static str)>,
+    }
+
+    fn change(operation: Operation, path: &'static str, content: &'static str) -> Change {
+        Change {
+            operation,
+            path,
+            content,
+            previous: None,
+        }
+    }
+
+    fn continued(
+        operation: Operation,
+        (path, content): (&'static str, &'static str),
+        previous: (&'static str, &'static str),
+    ) -> Change {
+        Change {
+            operation,
+            path,
+            content,
+            previous: Some(previous),
+        }
+    }
+
+    fn event(commit: u32) -> LifecycleEvent {
+        LifecycleEvent {
+            commit_id: format!("c{commit}"),
+            commit_time: FixedOffset::east_opt(0)
+                .unwrap()
+                .timestamp_opt(i64::from(commit) * 3600, 0)
+                .unwrap(),
+            author_name: String::new(),
+        }
+    }
+
+    fn record(commit: u32, change: &Change) -> Record {
+        let time = event(commit).commit_time;
+        let builder = RecordBuilder::new_base(
+            "org-repo".to_string(),
+            CommitTimes {
+                author_time: time,
+                commit_time: time,
+            },
+            Identity::default(),
+            Identity::default(),
+            format!("c{commit}"),
+            format!("c{}", commit - 1),
+            "message".to_string(),
+        );
+        let mut record = builder.build(
+            change.operation,
+            Some(change.path.to_string()),
+            "rust".to_string(),
+            "todo".to_string(),
+            change.content.to_string(),
+            Todo::parse(change.content.as_bytes()),
+        );
+        if let Some((path, content)) = change.previous {
+            record.previous_file_path = Some(path.to_string());
+            record.previous_content = Some(content.to_string());
+        }
+        record
+    }
+
+    /// Walk `commits`, given newest first as (commit, renames, changes).
+    fn walk(
+        stored_open: Vec<OpenLifecycle>,
+        commits: &[(u32, &[(&str, &str)], &[Change])],
+    ) -> Vec<Lifecycle> {
+        let collect = Collect::default();
+        let tracker = LifecycleTracker::new(
+            &collect,
+            "org-repo".to_string(),
+            "main".to_string(),
+            stored_open,
+        );
+        for (commit, renames, changes) in commits {
+            for (from, to) in *renames {
+                tracker.rename(from, to);
+            }
+            for change in *changes {
+                tracker.record(record(*commit, change));
+            }
+            tracker.finish_commit();
+        }
+        tracker.finish();
+        collect.lifecycles.into_inner()
+    }
+
+    fn stored(commit: u32, key: (&str, &str), latest: (&str, &str)) -> OpenLifecycle {
+        OpenLifecycle {
+            repo_name: "org-repo".to_string(),
+            ref_name: "main".to_string(),
+            file_path: key.0.to_string(),
+            content: key.1.to_string(),
+            introduced: event(commit),
+            latest_file_path: latest.0.to_string(),
+            latest_content: latest.1.to_string(),
+        }
+    }
+
+    fn commit_id(event: &Option<LifecycleEvent>) -> Option<&str> {
+        event.as_ref().map(|event| event.commit_id.as_str())
+    }
+
+    #[test]
+    fn remove_is_paired_with_older_add() {
+        let lifecycles = walk(
+            vec![],
+            &[
+                (3, &[], &[change(Operation::Remove, "a.rs", "TODO: a")]),
+                (2, &[], &[change(Operation::Add, "b.rs", "TODO: a")]),
+                (1, &[], &[change(Operation::Add, "a.rs", "TODO:  a")]),
+            ],
+        );
+
+        assert_eq!(lifecycles.len(), 2);
+        let alive = &lifecycles[0];
+        assert_eq!(alive.file_path, "b.rs");
+        assert_eq!(commit_id(&alive.introduced), Some("c2"));
+        assert!(alive.resolved.is_none());
+        assert_eq!(alive.age_secs, None);
+
+        let resolved = &lifecycles[1];
+        assert_eq!(
+            (resolved.file_path.as_str(), resolved.content.as_str()),
+            ("a.rs", "TODO: a")
+        );
+        assert_eq!(commit_id(&resolved.introduced), Some("c1"));
+        assert_eq!(commit_id(&resolved.resolved), Some("c3"));
+        assert_eq!(resolved.age_secs, Some(2 * 3600));
+        assert!(!resolved.closes_stored);
+    }
+
+    #[test]
+    fn remove_without_add_is_emitted_at_the_end() {
+        let lifecycles = walk(
+            vec![],
+            &[(2, &[], &[change(Operation::Remove, "a.rs", "TODO: a")])],
+        );
+
+        assert_eq!(lifecycles.len(), 1);
+        assert!(lifecycles[0].introduced.is_none());
+        assert_eq!(commit_id(&lifecycles[0].resolved), Some("c2"));
+    }
+
+    #[test]
+    fn modified_todo_continues_its_lifecycle() {
+        let lifecycles = walk(
+            vec![],
+            &[
+                (3, &[], &[change(Operation::Remove, "b.rs", "TODO: b")]),
+                (
+                    2,
+                    &[],
+                    &[continued(
+                        Operation::Move,
+                        ("b.rs", "TODO: b"),
+                        ("a.rs", "TODO: a"),
+                    )],
+                ),
+                (1, &[], &[change(Operation::Add, "a.rs", "TODO: a")]),
+            ],
+        );
+
+        assert_eq!(lifecycles.len(), 1);
+        let lifecycle = &lifecycles[0];
+        assert_eq!(
+            (lifecycle.file_path.as_str(), lifecycle.content.as_str()),
+            ("a.rs", "TODO: a")
+        );
+        assert_eq!(
+            (
+                lifecycle.latest_file_path.as_str(),
+                lifecycle.latest_content.as_str()
+            ),
+            ("b.rs", "TODO: b")
+        );
+        assert_eq!(commit_id(&lifecycle.resolved), Some("c3"));
+    }
+
+    #[test]
+    fn alive_todo_keeps_where_it_is_now() {
+        let lifecycles = walk(
+            vec![],
+            &[
+                (
+                    2,
+                    &[],
+                    &[continued(
+                        Operation::Modify,
+                        ("a.rs", "TODO: b"),
+                        ("a.rs", "TODO: a"),
+                    )],
+                ),
+                (1, &[], &[change(Operation::Add, "a.rs", "TODO: a")]),
+            ],
+        );
+
+        assert_eq!(lifecycles.len(), 1);
+        assert_eq!(lifecycles[0].content, "TODO: a");
+        assert_eq!(lifecycles[0].latest_content, "TODO: b");
+        assert!(lifecycles[0].resolved.is_none());
+    }
+
+    #[test]
+    fn removes_follow_renames() {
+        let lifecycles = walk(
+            vec![],
+            &[
+                (3, &[], &[change(Operation::Remove, "new.rs", "TODO: a")]),
+                (2, &[("old.rs", "new.rs")], &[]),
+                (1, &[], &[change(Operation::Add, "old.rs", "TODO: a")]),
+            ],
+        );
+
+        assert_eq!(lifecycles.len(), 1);
+        assert_eq!(lifecycles[0].file_path, "old.rs");
+        assert_eq!(lifecycles[0].latest_file_path, "new.rs");
+        assert_eq!(commit_id(&lifecycles[0].introduced), Some("c1"));
+        assert_eq!(commit_id(&lifecycles[0].resolved), Some("c3"));
+    }
+
+    #[test]
+    fn stored_open_todo_is_resolved_where_it_was_left() {
+        let open = stored(1, ("a.rs", "TODO: a"), ("b.rs", "TODO: b"));
+        let lifecycles = walk(
+            vec![open],
+            &[(5, &[], &[change(Operation::Remove, "b.rs", "TODO: b")])],
+        );
+
+        assert_eq!(lifecycles.len(), 1);
+        let lifecycle = &lifecycles[0];
+        assert_eq!(
+            (lifecycle.file_path.as_str(), lifecycle.content.as_str()),
+            ("a.rs", "TODO: a")
+        );
+        assert_eq!(commit_id(&lifecycle.introduced), Some("c1"));
+        assert_eq!(commit_id(&lifecycle.resolved), Some("c5"));
+        assert_eq!(lifecycle.age_secs, Some(4 * 3600));
+        assert!(lifecycle.closes_stored);
+    }
+
+    #[test]
+    fn stored_open_todo_follows_changes_after_the_cursor() {
+        let open = stored(1, ("a.rs", "TODO: a"), ("a.rs", "TODO: a"));
+        let lifecycles = walk(
+            vec![open],
+            &[
+                (
+                    6,
+                    &[],
+                    &[continued(
+                        Operation::Modify,
+                        ("c.rs", "TODO: b"),
+                        ("c.rs", "TODO: a"),
+                    )],
+                ),
+                (5, &[("a.rs", "c.rs")], &[]),
+            ],
+        );
+
+        assert_eq!(lifecycles.len(), 1);
+        let lifecycle = &lifecycles[0];
+        assert_eq!(lifecycle.file_path, "a.rs");
+        assert_eq!(commit_id(&lifecycle.introduced), Some("c1"));
+        assert!(lifecycle.resolved.is_none());
+        assert_eq!(
+            (
+                lifecycle.latest_file_path.as_str(),
+                lifecycle.latest_content.as_str()
+            ),
+            ("c.rs", "TODO: b")
+        );
+        assert!(lifecycle.closes_stored);
+
+        // the next walk finds it where this one left it
+        let open = stored(1, ("a.rs", "TODO: a"), ("c.rs", "TODO: b"));
+        let lifecycles = walk(
+            vec![open],
+            &[(7, &[], &[change(Operation::Remove, "c.rs", "TODO: b")])],
+        );
+        assert_eq!(lifecycles.len(), 1);
+        assert_eq!(commit_id(&lifecycles[0].introduced), Some("c1"));
+        assert_eq!(commit_id(&lifecycles[0].resolved), Some("c7"));
+        assert!(lifecycles[0].closes_stored);
+    }
+
+    #[test]
+    fn stored_open_todo_walked_again_is_replaced_only_if_changed() {
+        let unchanged = stored(1, ("a.rs", "TODO: a"), ("a.rs", "TODO: a"));
+        let resolved = stored(1, ("b.rs", "TODO: b"), ("b.rs", "TODO: b"));
+        let lifecycles = walk(
+            vec![unchanged, resolved],
+            &[
+                (2, &[], &[change(Operation::Remove, "b.rs", "TODO: b")]),
+                (
+                    1,
+                    &[],
+                    &[
+                        change(Operation::Add, "a.rs", "TODO: a"),
+                        change(Operation::Add, "b.rs", "TODO: b"),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(lifecycles.len(), 2);
+        assert_eq!(lifecycles[0].file_path, "a.rs");
+        assert!(!lifecycles[0].closes_stored);
+        assert_eq!(lifecycles[1].file_path, "b.rs");
+        assert_eq!(commit_id(&lifecycles[1].resolved), Some("c2"));
+        assert!(lifecycles[1].closes_stored);
+    }
+}
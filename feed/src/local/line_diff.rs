@@ -0,0 +1,20 @@
+//! Line diff that keeps the position of each hunk.
+
+use std::ops::Range;
+
+use gix_diff::blob::intern::InternedInput;
+use gix_diff::blob::sources::byte_lines;
+use gix_diff::blob::{diff, Algorithm};
+
+/// Changed hunks between two blobs, as (0-based line range in old, 0-based
+/// line range in new).
+pub fn hunks(old: &[u8], new: &[u8]) -> Vec<(Range<u32>, Range<u32>)> {
+    let input = InternedInput::new(byte_lines(old), byte_lines(new));
+    let mut hunks = vec![];
+    diff(
+        Algorithm::Histogram,
+        &input,
+        |before: Range<u32>, after: Range<u32>| hunks.push((before, after)),
+    );
+    hunks
+}
@@ -0,0 +1,156 @@
+//! Settings a project declares in a `.greptodo.toml` at its repo root.
+//!
+//! ```toml
+//! # todo marker words, replacing the server's patterns unless a request
+//! # gives its own
+//! markers = ["TODO", "FIXME", "SAFETY"]
+//! # globs of paths not to scan, on top of the server's
+//! ignore = ["docs/**", "examples/**"]
+//!
+//! # canonical owner of each assignee alias
+//! [owners]
+//! bob = "robert"
+//! ```
+//!
+//! The file is read from each walked commit, so a change to it applies from
+//! the commit that made it on.
+
+use std::collections::HashMap;
+
+use gix_hash::ObjectId;
+use regex::bytes::RegexSet;
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use super::filter::PathFilter;
+use super::{FetchRequest, Todo};
+use crate::error::{FeedResult, InvalidPatternSnafu, InvalidRepoConfigSnafu};
+
+pub const CONFIG_FILE: &str = ".greptodo.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RepoConfig {
+    pub markers: Vec<String>,
+    pub ignore: Vec<String>,
+    pub owners: HashMap<String, String>,
+}
+
+impl RepoConfig {
+    pub fn parse(data: &[u8]) -> FeedResult<Self> {
+        toml::from_str(&String::from_utf8_lossy(data)).context(InvalidRepoConfigSnafu)
+    }
+}
+
+/// Settings in effect for one commit: server defaults with the repo config,
+/// if any, merged over, and patterns of the request over both.
+#[derive(Debug)]
+pub struct ScanConfig {
+    /// Blob id of the merged `.greptodo.toml`
+    pub rev: Option<String>,
+    pub patterns: Vec<String>,
+    pub regex: RegexSet,
+    pub filter: PathFilter,
+    owners: HashMap<String, String>,
+}
+
+/// Pattern matching comment text that starts with `marker` as a whole word,
+/// so `TODO` doesn't match `Todos` or `todolist`.
+fn marker_pattern(marker: &str) -> String {
+    let boundary = if marker.ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+        "\\b"
+    } else {
+        ""
+    };
+    format!("(?i)^\\W*{}{boundary}", regex::escape(marker))
+}
+
+impl ScanConfig {
+    pub fn new(
+        req: &FetchRequest,
+        gitattributes: Option<&[u8]>,
+        repo_config: Option<(ObjectId, RepoConfig)>,
+    ) -> FeedResult<Self> {
+        let (rev, repo_config) = match repo_config {
+            Some((id, config)) => (Some(id.to_string()), config),
+            None => (None, RepoConfig::default()),
+        };
+
+        let patterns = if req.patterns_requested || repo_config.markers.is_empty() {
+            req.patterns.clone()
+        } else {
+            repo_config
+                .markers
+                .iter()
+                .map(|marker| marker_pattern(marker))
+                .collect()
+        };
+        let regex = RegexSet::new(&patterns).context(InvalidPatternSnafu)?;
+
+        let mut exclude = req.exclude.clone();
+        exclude.extend(repo_config.ignore);
+        let filter = PathFilter::new(&req.include, &exclude, req.max_blob_size, gitattributes)?;
+
+        Ok(Self {
+            rev,
+            patterns,
+            regex,
+            filter,
+            owners: repo_config.owners,
+        })
+    }
+
+    /// Parse a todo comment, with the assignee mapped to its canonical owner.
+    pub fn parse_todo(&self, comment: &[u8]) -> Todo {
+        let mut todo = Todo::parse(comment);
+        todo.assignee = todo
+            .assignee
+            .map(|assignee| self.owners.get(&assignee).cloned().unwrap_or(assignee));
+        todo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::DEFAULT_PATTERNS;
+
+    const FALSE_POSITIVES: &[&str] = &[
+        "Todos an earlier walk stored as open",
+        "todolist of the week",
+        "Hacker news",
+        "xxxl sizes",
+        "Fixmes are counted",
+    ];
+
+    #[test]
+    fn default_patterns_match_whole_markers() {
+        let regex = RegexSet::new(DEFAULT_PATTERNS).unwrap();
+
+        for text in [
+            "TODO: fix",
+            " todo(bob) fix",
+            "FIXME",
+            "* hack - fix",
+            "XXX!",
+        ] {
+            assert!(regex.is_match(text.as_bytes()), "{text}");
+        }
+        for text in FALSE_POSITIVES {
+            assert!(!regex.is_match(text.as_bytes()), "{text}");
+        }
+    }
+
+    #[test]
+    fn markers_match_whole_words() {
+        let markers = ["TODO", "FIXME", "HACK", "XXX", "@todo:"];
+        let regex = RegexSet::new(markers.iter().map(|m| marker_pattern(m))).unwrap();
+
+        for text in ["TODO: fix", "hack it", "@todo: fix", "@TODO:fix"] {
+            assert!(regex.is_match(text.as_bytes()), "{text}");
+        }
+        for text in FALSE_POSITIVES {
+            assert!(!regex.is_match(text.as_bytes()), "{text}");
+        }
+    }
+}
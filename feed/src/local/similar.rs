@@ -0,0 +1,276 @@
+//! Decide whether two todo lines are the same todo.
+
+use std::collections::HashMap;
+
+use crate::schema::{Operation, Record};
+
+/// Minimal similarity for a removed and an added todo in one hunk to be
+/// taken as one modified todo.
+const MODIFY_THRESHOLD: f32 = 0.6;
+
+/// Collapse whitespace so re-indented todos are still the same one.
+pub fn normalize(content: &str) -> String {
+    content.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Dice coefficient of character bigrams of the normalized contents, in
+/// `[0, 1]`.
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let a = bigrams(&normalize(a));
+    let b = bigrams(&normalize(b));
+    let total = a.values().sum::<usize>() + b.values().sum::<usize>();
+    if total == 0 {
+        return 1.0;
+    }
+
+    let common = a
+        .iter()
+        .map(|(bigram, count)| (*count).min(*b.get(bigram).unwrap_or(&0)))
+        .sum::<usize>();
+    (2 * common) as f32 / total as f32
+}
+
+fn bigrams(s: &str) -> HashMap<(char, char), usize> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut bigrams = HashMap::new();
+    for pair in chars.windows(2) {
+        *bigrams.entry((pair[0], pair[1])).or_default() += 1;
+    }
+    bigrams
+}
+
+/// Similarity of two todos by their messages, as the comment delimiter and
+/// marker they share would make any two short todos look alike. Todos
+/// without a message are compared by their whole line.
+fn todo_similarity(before: &Record, after: &Record) -> f32 {
+    if before.message.is_empty() || after.message.is_empty() {
+        similarity(&before.content, &after.content)
+    } else {
+        similarity(&before.message, &after.message)
+    }
+}
+
+/// Pair removed and added todos of one hunk. A pair that is similar enough
+/// becomes one [Operation::Modify] record.
+pub fn pair_in_hunk(removed: Vec<Record>, added: Vec<Record>) -> Vec<Record> {
+    let mut added = added.into_iter().map(Some).collect::<Vec<_>>();
+    let mut result = Vec::with_capacity(removed.len() + added.len());
+
+    for before in removed {
+        let best = added
+            .iter()
+            .enumerate()
+            .filter_map(|(i, after)| {
+                let after = after.as_ref()?;
+                Some((i, todo_similarity(&before, after)))
+            })
+            .filter(|(_, score)| *score >= MODIFY_THRESHOLD)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match best {
+            Some((i, _)) => {
+                let mut after = added[i].take().unwrap();
+                after.operation = Operation::Modify;
+                after.previous_content = Some(before.content);
+                after.old_line_number = before.old_line_number;
+                result.push(after);
+            }
+            None => result.push(before),
+        }
+    }
+
+    result.extend(added.into_iter().flatten());
+    result
+}
+
+/// Pair removed and added todos with the same content across hunks and files
+/// of one commit. Pairs in different files become [Operation::Move], pairs in
+/// one file become [Operation::Modify].
+pub fn pair_in_commit(records: Vec<Record>) -> Vec<Record> {
+    let mut removed: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, record) in records.iter().enumerate() {
+        if record.operation == Operation::Remove {
+            removed
+                .entry(normalize(&record.content))
+                .or_default()
+                .push(i);
+        }
+    }
+
+    // (index of add, index of remove)
+    let mut pairs = vec![];
+    for (i, record) in records.iter().enumerate() {
+        if record.operation != Operation::Add {
+            continue;
+        }
+        let Some(candidates) = removed.get_mut(&normalize(&record.content)) else {
+            continue;
+        };
+        if candidates.is_empty() {
+            continue;
+        }
+        // prefer the one in the same file
+        let position = candidates
+            .iter()
+            .position(|c| records[*c].file_path == record.file_path)
+            .unwrap_or(0);
+        pairs.push((i, candidates.remove(position)));
+    }
+
+    let mut records = records.into_iter().map(Some).collect::<Vec<_>>();
+    for (add, remove) in pairs {
+        let before = records[remove].take().unwrap();
+        let after = records[add].as_mut().unwrap();
+        if before.file_path == after.file_path {
+            after.operation = Operation::Modify;
+        } else {
+            after.operation = Operation::Move;
+            after.previous_file_path = before.file_path;
+        }
+        after.previous_content = Some(before.content);
+        after.old_line_number = before.old_line_number;
+    }
+
+    records.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::*;
+    use crate::local::{Identity, Todo};
+    use crate::schema::{CommitTimes, RecordBuilder};
+
+    fn record(operation: Operation, line_number: u32, content: &str) -> Record {
+        let time = FixedOffset::east_opt(0)
+            .unwrap()
+            .timestamp_opt(0, 0)
+            .unwrap();
+        let builder = RecordBuilder::new_base(
+            "org-repo".to_string(),
+            CommitTimes {
+                author_time: time,
+                commit_time: time,
+            },
+            Identity::default(),
+            Identity::default(),
+            "commit".to_string(),
+            "parent".to_string(),
+            "message".to_string(),
+        );
+        let comment = content.trim_start().trim_start_matches("//");
+        let mut record = builder.build(
+            operation,
+            Some("src/main.rs".to_string()),
+            "rust".to_string(),
+            "todo".to_string(),
+            content.to_string(),
+            Todo::parse(comment.as_bytes()),
+        );
+        if operation == Operation::Remove {
+            record.old_line_number = Some(line_number);
+        } else {
+            record.new_line_number = Some(line_number);
+        }
+        record
+    }
+
+    fn operations(records: &[Record]) -> Vec<(Operation, &str)> {
+        records
+            .iter()
+            .map(|record| (record.operation, record.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn reworded_todo_is_modified() {
+        let records = pair_in_hunk(
+            vec![record(
+                Operation::Remove,
+                3,
+                "// TODO: handle the error case",
+            )],
+            vec![record(
+                Operation::Add,
+                3,
+                "    // TODO: handle the error cases",
+            )],
+        );
+        assert_eq!(
+            operations(&records),
+            [(Operation::Modify, "    // TODO: handle the error cases")]
+        );
+        assert_eq!(
+            records[0].previous_content.as_deref(),
+            Some("// TODO: handle the error case")
+        );
+        assert_eq!(records[0].old_line_number, Some(3));
+        assert_eq!(records[0].new_line_number, Some(3));
+    }
+
+    #[test]
+    fn short_unrelated_todos_are_not_paired() {
+        let records = pair_in_hunk(
+            vec![record(Operation::Remove, 1, "// TODO: fix")],
+            vec![record(Operation::Add, 1, "// TODO: docs")],
+        );
+        assert_eq!(
+            operations(&records),
+            [
+                (Operation::Remove, "// TODO: fix"),
+                (Operation::Add, "// TODO: docs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn most_similar_todo_is_paired() {
+        let records = pair_in_hunk(
+            vec![record(
+                Operation::Remove,
+                5,
+                "// TODO: retry failed requests",
+            )],
+            vec![
+                record(Operation::Add, 5, "// TODO: log failed requests"),
+                record(Operation::Add, 6, "// TODO: retry the failed requests"),
+            ],
+        );
+        assert_eq!(
+            operations(&records),
+            [
+                (Operation::Modify, "// TODO: retry the failed requests"),
+                (Operation::Add, "// TODO: log failed requests"),
+            ]
+        );
+    }
+
+    #[test]
+    fn todos_without_message_are_compared_by_line() {
+        let records = pair_in_hunk(
+            vec![record(Operation::Remove, 2, "// TODO")],
+            vec![record(Operation::Add, 2, "    // TODO")],
+        );
+        assert_eq!(operations(&records), [(Operation::Modify, "    // TODO")]);
+    }
+
+    #[test]
+    fn same_todo_in_another_file_is_moved() {
+        let mut moved = record(Operation::Add, 10, "// TODO: split this module");
+        moved.file_path = Some("src/lib.rs".to_string());
+        let records = pair_in_commit(vec![
+            record(Operation::Remove, 4, "// TODO: split this module"),
+            moved,
+        ]);
+        assert_eq!(
+            operations(&records),
+            [(Operation::Move, "// TODO: split this module")]
+        );
+        assert_eq!(
+            records[0].previous_file_path.as_deref(),
+            Some("src/main.rs")
+        );
+        assert_eq!(records[0].old_line_number, Some(4));
+    }
+}
@@ -0,0 +1,147 @@
+//! Parse the structure out of a todo comment.
+//!
+//! Recognized forms, which can be combined:
+//! - `TODO(alice): message` assigns the todo to `alice`
+//! - `TODO(#1234)` or a `https://...` url links the todo to an issue
+//! - `TODO[2026-12-01]` sets a deadline
+
+use std::sync::OnceLock;
+
+use chrono::NaiveDate;
+use regex::bytes::Regex;
+use serde::Serialize;
+
+static HEAD_RE: OnceLock<Regex> = OnceLock::new();
+static META_RE: OnceLock<Regex> = OnceLock::new();
+static ISSUE_RE: OnceLock<Regex> = OnceLock::new();
+static DATE_RE: OnceLock<Regex> = OnceLock::new();
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Todo {
+    /// Marker word in upper case, like `TODO` or `FIXME`
+    pub marker: String,
+    pub assignee: Option<String>,
+    /// Issue numbers (`#1234`) or urls
+    pub issues: Vec<String>,
+    /// Deadline from `YYYY-MM-DD`
+    pub deadline: Option<NaiveDate>,
+    /// The message with marker and metadata stripped
+    pub message: String,
+}
+
+impl Todo {
+    /// Parse the text of a comment, with comment delimiters already removed.
+    pub fn parse(comment: &[u8]) -> Todo {
+        let head_re = HEAD_RE.get_or_init(|| {
+            Regex::new(r"^\W*(?P<marker>\w+)(?P<meta>(?:\s*(?:\([^)]*\)|\[[^\]]*\]))*)\s*[:\-]?\s*(?P<message>.*)$")
+                .unwrap()
+        });
+        let meta_re = META_RE.get_or_init(|| Regex::new(r"\(([^)]*)\)|\[([^\]]*)\]").unwrap());
+        let issue_re = ISSUE_RE.get_or_init(|| Regex::new(r"#\d+|https?://[^\s,)\]]+").unwrap());
+        let date_re = DATE_RE.get_or_init(|| Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap());
+
+        let Some(caps) = head_re.captures(comment) else {
+            return Todo {
+                message: String::from_utf8_lossy(comment).trim().to_string(),
+                ..Default::default()
+            };
+        };
+
+        let mut todo = Todo {
+            marker: String::from_utf8_lossy(&caps["marker"]).to_uppercase(),
+            message: String::from_utf8_lossy(&caps["message"]).trim().to_string(),
+            ..Default::default()
+        };
+
+        for meta in meta_re.captures_iter(&caps["meta"]) {
+            let inner = meta.get(1).or_else(|| meta.get(2)).unwrap().as_bytes();
+            let inner = String::from_utf8_lossy(inner);
+            for item in inner.split([',', ' ']).map(str::trim) {
+                if item.is_empty() {
+                    continue;
+                }
+                if issue_re.is_match(item.as_bytes()) {
+                    todo.issues.push(item.to_string());
+                } else if date_re.is_match(item.as_bytes()) {
+                    // a date that doesn't exist, like `2026-13-45`, is dropped
+                    if let Ok(date) = NaiveDate::parse_from_str(item, "%Y-%m-%d") {
+                        todo.deadline = Some(date);
+                    }
+                } else if todo.assignee.is_none() {
+                    todo.assignee = Some(item.trim_start_matches('@').to_string());
+                }
+            }
+        }
+
+        // issues mentioned in the message are linked as well
+        for issue in issue_re.find_iter(&caps["message"]) {
+            let issue = String::from_utf8_lossy(issue.as_bytes()).to_string();
+            if !todo.issues.contains(&issue) {
+                todo.issues.push(issue);
+            }
+        }
+
+        todo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(comment: &str) -> Todo {
+        Todo::parse(comment.as_bytes())
+    }
+
+    #[test]
+    fn marker_and_message() {
+        assert_eq!(
+            parse(" todo: handle the error"),
+            Todo {
+                marker: "TODO".to_string(),
+                message: "handle the error".to_string(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(parse(" FIXME - flaky").message, "flaky");
+        assert_eq!(parse(" TODO").message, "");
+    }
+
+    #[test]
+    fn assignee() {
+        let todo = parse(" TODO(alice): split this");
+        assert_eq!(todo.assignee.as_deref(), Some("alice"));
+        assert_eq!(todo.message, "split this");
+        assert_eq!(parse(" TODO(@bob)").assignee.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn issues() {
+        let todo = parse(" FIXME(#12): see https://github.com/org/repo/issues/3");
+        assert_eq!(todo.issues, ["#12", "https://github.com/org/repo/issues/3"]);
+        assert_eq!(todo.assignee, None);
+        // mentioned twice, linked once
+        assert_eq!(parse(" TODO(#7): #7").issues, ["#7"]);
+    }
+
+    #[test]
+    fn deadline() {
+        assert_eq!(
+            parse(" TODO[2026-12-01]: ship it").deadline,
+            NaiveDate::from_ymd_opt(2026, 12, 1)
+        );
+        assert_eq!(parse(" TODO[2026-13-45]: later").deadline, None);
+        assert_eq!(parse(" TODO[2026-02-29]").deadline, None);
+        assert_eq!(parse(" TODO: by 2026-12-01").deadline, None);
+    }
+
+    #[test]
+    fn combined_metadata() {
+        let todo = parse(" HACK(carol, #5)[2027-01-31] - remove the workaround");
+        assert_eq!(todo.marker, "HACK");
+        assert_eq!(todo.assignee.as_deref(), Some("carol"));
+        assert_eq!(todo.issues, ["#5"]);
+        assert_eq!(todo.deadline, NaiveDate::from_ymd_opt(2027, 1, 31));
+        assert_eq!(todo.message, "remove the workaround");
+    }
+}
@@ -13,12 +13,12 @@ use tracing_subscriber::{fmt, EnvFilter};
 use crate::config::FeedConfig;
 
 mod config;
-mod conn;
 mod consumer;
 mod error;
 mod local;
 mod schema;
 mod server;
+mod storage;
 
 #[tokio::main]
 async fn main() {
@@ -31,7 +31,7 @@ async fn main() {
 
     info!("{config:?}");
     let addr = SocketAddr::new(config.addr.parse().unwrap(), config.port);
-    let app = server::build_server(config.repo_dir).await;
+    let app = server::build_server(&config).await;
 
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
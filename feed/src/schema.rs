@@ -1,41 +1,158 @@
 use std::fmt::Display;
 
-/// `CREATE TABLE` clause:
-/// ```sql
-/// CREATE TABLE records (
-///     commit_time String,
-///     repo_name String,
-///     author_name String,
-///     author_email String,
-///     operation String,
-///     file_path String,
-///     commit_id String,
-///     commit_message String,
-///     content String,
-///     calc_time TIMESTAMP TIME INDEX DEFAULT CURRENT_TIMESTAMP,
-///     PRIMARY KEY (repo_name, commit_id, file_path, content)
-/// );
-/// ```
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::local::{Attribution, Identity, Todo};
+
+/// A table rows are written to.
+#[derive(Debug)]
+pub struct Table {
+    pub name: &'static str,
+    /// Columns given on insert. Others are filled by their default value
+    pub columns: &'static [&'static str],
+}
+
+pub const RECORDS: Table = Table {
+    name: "records",
+    columns: &[
+        "repo_name",
+        "commit_time",
+        "commit_utc_offset",
+        "author_time",
+        "author_utc_offset",
+        "author_name",
+        "author_email",
+        "raw_author_name",
+        "raw_author_email",
+        "operation",
+        "file_path",
+        "commit_id",
+        "commit_message",
+        "content",
+        "pattern",
+        "language",
+        "marker",
+        "assignee",
+        "issues",
+        "deadline",
+        "message",
+        "previous_file_path",
+        "previous_content",
+        "old_line_number",
+        "new_line_number",
+        "context",
+        "link",
+        "ref_name",
+        "config_rev",
+    ],
+};
+
+pub const LIFECYCLES: Table = Table {
+    name: "lifecycles",
+    columns: &[
+        "repo_name",
+        "ref_name",
+        "file_path",
+        "content",
+        "introduced_commit",
+        "introduced_time",
+        "introducer",
+        "resolved_commit",
+        "resolved_time",
+        "resolver",
+        "age_secs",
+        "first_seen_time",
+        "latest_file_path",
+        "latest_content",
+    ],
+};
+
+pub const SYNC_CURSORS: Table = Table {
+    name: "sync_cursors",
+    columns: &[
+        "repo_name",
+        "ref_name",
+        "traversal",
+        "options_hash",
+        "commit_id",
+    ],
+};
+
+/// Value of one column. Nulls keep their type, as some databases need it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Int(Option<i64>),
+    /// Timestamp in UTC
+    Time(Option<NaiveDateTime>),
+}
+
+/// Row of table `records`, created by the migrations in `storage::migration`.
 #[derive(Debug)]
 pub struct Record {
     pub repo_name: String,
-    pub commit_time: String,
+    /// Committer time, in the committer's UTC offset
+    pub commit_time: DateTime<FixedOffset>,
+    /// Author time, in the author's UTC offset
+    pub author_time: DateTime<FixedOffset>,
+    /// Canonical author, after `.mailmap` and the server's aliases
     pub author_name: String,
     pub author_email: String,
+    /// Author as committed
+    pub raw_author_name: String,
+    pub raw_author_email: String,
     pub operation: Operation,
     pub file_path: Option<String>,
     pub commit_id: String,
     pub commit_messaage: String,
     /// Todo content
     pub content: String,
+    /// The marker pattern that matched this todo
+    pub pattern: String,
+    /// Language of the file, decides which comment syntax is recognized
+    pub language: String,
+    /// Marker word in upper case, like `TODO` or `FIXME`
+    pub marker: String,
+    /// Owner in `TODO(owner)`
+    pub assignee: Option<String>,
+    /// Linked issue numbers or urls
+    pub issues: Vec<String>,
+    /// Deadline from `TODO[2026-12-01]`
+    pub deadline: Option<NaiveDate>,
+    /// Todo message without marker and metadata
+    pub message: String,
+    /// Path the todo comes from, if it's moved to another file or the file
+    /// is renamed or copied
+    pub previous_file_path: Option<String>,
+    /// Content before modified or moved
+    pub previous_content: Option<String>,
+    /// 1-based line number in the parent commit, for removed, modified or
+    /// moved todos
+    pub old_line_number: Option<u32>,
+    /// 1-based line number in this commit, for all but removed todos
+    pub new_line_number: Option<u32>,
+    /// Lines around the todo, including itself
+    pub context: String,
+    /// Link to the todo on the remote, like
+    /// `https://github.com/{org}/{repo}/blob/{commit}/{path}#L{n}`
+    pub link: Option<String>,
+    /// Branch, tag or commit that is scanned
+    pub ref_name: String,
+    /// Blob id of the `.greptodo.toml` in effect at the commit, if any
+    pub config_rev: Option<String>,
     /// Time this record is calculated
     pub calc_time: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operation {
     Add,
     Remove,
+    /// Todo is changed in place, like re-indented or reworded
+    Modify,
+    /// Todo is moved to another file
+    Move,
 }
 
 impl Display for Operation {
@@ -43,35 +160,47 @@ impl Display for Operation {
         match self {
             Operation::Add => f.write_str("add"),
             Operation::Remove => f.write_str("remove"),
+            Operation::Modify => f.write_str("modify"),
+            Operation::Move => f.write_str("move"),
         }
     }
 }
 
+/// Author and committer time of a commit.
+#[derive(Debug, Clone, Copy)]
+pub struct CommitTimes {
+    pub author_time: DateTime<FixedOffset>,
+    pub commit_time: DateTime<FixedOffset>,
+}
+
 #[derive(Debug)]
 pub struct RecordBuilder {
     pub repo_name: String,
-    pub commit_time: String,
-    pub author_name: String,
-    pub author_email: String,
+    pub times: CommitTimes,
+    pub author: Identity,
+    pub raw_author: Identity,
     pub commit_id: String,
+    pub parent_commit_id: String,
     pub commit_message: String,
 }
 
 impl RecordBuilder {
     pub fn new_base(
         repo_name: String,
-        commit_time: String,
-        author_name: String,
-        author_email: String,
+        times: CommitTimes,
+        author: Identity,
+        raw_author: Identity,
         commit_id: String,
+        parent_commit_id: String,
         commit_message: String,
     ) -> Self {
         Self {
             repo_name,
-            commit_time,
-            author_name,
-            author_email,
+            times,
+            author,
+            raw_author,
             commit_id,
+            parent_commit_id,
             commit_message,
         }
     }
@@ -80,19 +209,132 @@ impl RecordBuilder {
         &self,
         operation: Operation,
         file_path: Option<String>,
+        language: String,
+        pattern: String,
         content: String,
+        todo: Todo,
     ) -> Record {
         Record {
             repo_name: self.repo_name.clone(),
-            commit_time: self.commit_time.clone(),
-            author_name: self.author_name.clone(),
-            author_email: self.author_email.clone(),
+            commit_time: self.times.commit_time,
+            author_time: self.times.author_time,
+            author_name: self.author.name.clone(),
+            author_email: self.author.email.clone(),
+            raw_author_name: self.raw_author.name.clone(),
+            raw_author_email: self.raw_author.email.clone(),
             operation,
             file_path,
             commit_id: self.commit_id.clone(),
             commit_messaage: self.commit_message.clone(),
             content,
+            pattern,
+            language,
+            marker: todo.marker,
+            assignee: todo.assignee,
+            issues: todo.issues,
+            deadline: todo.deadline,
+            message: todo.message,
+            previous_file_path: None,
+            previous_content: None,
+            old_line_number: None,
+            new_line_number: None,
+            context: String::new(),
+            link: None,
+            ref_name: String::new(),
+            config_rev: None,
             calc_time: String::new(),
         }
     }
 }
+
+/// Life of one todo, from the commit that introduced it to the one that
+/// resolved it.
+///
+/// Row of table `lifecycles`, created by the migrations in `storage::migration`.
+#[derive(Debug)]
+pub struct Lifecycle {
+    pub repo_name: String,
+    pub ref_name: String,
+    /// Path of the file when the todo is introduced
+    pub file_path: String,
+    /// Normalized todo content
+    pub content: String,
+    /// `None` if the todo is introduced before the scanned range
+    pub introduced: Option<LifecycleEvent>,
+    /// `None` if the todo is still alive
+    pub resolved: Option<LifecycleEvent>,
+    /// Seconds between introduced and resolved, if both are known
+    pub age_secs: Option<i64>,
+    /// Path of the file when the todo is resolved, or at the walked revision
+    /// if it is still alive
+    pub latest_file_path: String,
+    /// Normalized todo content when resolved, or at the walked revision
+    pub latest_content: String,
+    /// Whether this replaces a todo an earlier walk stored as open. That row
+    /// is deleted before this one is written
+    pub closes_stored: bool,
+}
+
+/// What a sync cursor is kept for. Walks in another traversal mode or with
+/// other scan options find other todos, so they don't share a cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorKey {
+    pub repo_name: String,
+    pub ref_name: String,
+    /// Name of the traversal mode
+    pub traversal: String,
+    /// SHA-1 in hex of the options deciding which todos are found
+    pub options_hash: String,
+}
+
+/// A todo an earlier walk stored as introduced but not resolved.
+#[derive(Debug, Clone)]
+pub struct OpenLifecycle {
+    pub repo_name: String,
+    pub ref_name: String,
+    pub file_path: String,
+    /// Normalized todo content
+    pub content: String,
+    pub introduced: LifecycleEvent,
+    /// Path and normalized content at the revision of that walk, which a
+    /// later walk finds the todo under
+    pub latest_file_path: String,
+    pub latest_content: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LifecycleEvent {
+    pub commit_id: String,
+    pub commit_time: DateTime<FixedOffset>,
+    pub author_name: String,
+}
+
+impl From<&Record> for LifecycleEvent {
+    fn from(record: &Record) -> Self {
+        Self {
+            commit_id: record.commit_id.clone(),
+            commit_time: record.commit_time,
+            author_name: record.author_name.clone(),
+        }
+    }
+}
+
+/// One todo present in the tree of a revision.
+#[derive(Debug, Serialize)]
+pub struct SnapshotTodo {
+    pub file_path: String,
+    /// 1-based line number
+    pub line_number: usize,
+    /// Link to the todo on the remote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    pub language: String,
+    /// The marker pattern that matched this todo
+    pub pattern: String,
+    pub content: String,
+    #[serde(flatten)]
+    pub todo: Todo,
+    /// The commit that introduced this todo, if blame is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub introduced: Option<Attribution>,
+}
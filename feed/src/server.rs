@@ -1,6 +1,9 @@
+mod jobs;
 mod last_commit;
+mod operation_count;
 mod some_files;
 mod state;
+mod todos;
 mod update_repo;
 
 use axum::http::Method;
@@ -9,23 +12,34 @@ use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 
 use self::some_files::some_files;
+use crate::config::FeedConfig;
+use crate::server::jobs::{job, job_events};
 use crate::server::last_commit::last_commit;
+use crate::server::operation_count::operation_count;
 use crate::server::state::ServerState;
+use crate::server::todos::todos;
 use crate::server::update_repo::update_repo;
 
-pub async fn build_server(local_root: String) -> Router {
-    let state = ServerState::new(local_root).await.unwrap();
+pub async fn build_server(config: &FeedConfig) -> Router {
+    let state = ServerState::new(config).await.unwrap();
 
     let router = Router::new()
         .route("/update_repo", routing::post(update_repo))
         .route("/last_commit", routing::post(last_commit))
         .route("/some_files", routing::post(some_files))
+        .route("/todos", routing::get(todos).post(todos))
+        .route("/jobs/:id", routing::get(job))
+        .route("/jobs/:id/events", routing::get(job_events))
+        .route(
+            "/operation_count",
+            routing::get(operation_count).post(operation_count),
+        )
         .with_state(state);
 
     Router::new().nest("/api", router).layer(
         ServiceBuilder::new().layer(
             CorsLayer::new()
-                .allow_methods([Method::POST])
+                .allow_methods([Method::GET, Method::POST])
                 .allow_origin(Any),
         ),
     )
@@ -0,0 +1,125 @@
+//! Progress and outcome of an update job.
+
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::Json;
+use futures::{stream, Stream};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use crate::consumer::ProgressEvent;
+use crate::server::state::{Job, JobPhase, JobStatus, ServerState};
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct JobResponse {
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    job: Option<JobStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn job(State(state): State<ServerState>, Path(id): Path<u64>) -> Json<JobResponse> {
+    let response = match state.job(id) {
+        Some(job) => JobResponse {
+            job: Some(job.status()),
+            error: None,
+        },
+        None => JobResponse {
+            job: None,
+            error: Some(format!("Job {id} not found")),
+        },
+    };
+
+    Json(response)
+}
+
+/// Stream the progress of a job as server-sent events:
+///
+/// - `status`: the whole [JobStatus], first on connect, then on each phase
+///   change and whenever the listener fell behind and missed events
+/// - `commit`: a commit was walked
+/// - `record`: a todo change was found
+///
+/// The stream ends after the status of the finished job.
+#[axum_macros::debug_handler]
+pub async fn job_events(
+    State(state): State<ServerState>,
+    Path(id): Path<u64>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Json<JobResponse>> {
+    let Some(job) = state.job(id) else {
+        return Err(Json(JobResponse {
+            job: None,
+            error: Some(format!("Job {id} not found")),
+        }));
+    };
+
+    let (phase, events) = job.subscribe();
+    let listener = Listener {
+        job,
+        phase,
+        events,
+        started: false,
+        finished: false,
+    };
+    let stream = stream::unfold(listener, |mut listener| async move {
+        let event = listener.next_event().await?;
+        Some((event, listener))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// One connection to [job_events].
+struct Listener {
+    job: Arc<Job>,
+    phase: watch::Receiver<JobPhase>,
+    events: broadcast::Receiver<ProgressEvent>,
+    started: bool,
+    finished: bool,
+}
+
+impl Listener {
+    /// `None` once the job finished and its last status was sent.
+    async fn next_event(&mut self) -> Option<Result<Event, axum::Error>> {
+        if self.finished {
+            return None;
+        }
+        if !self.started {
+            self.started = true;
+            return Some(self.status());
+        }
+
+        // events buffered before a phase change are sent before it
+        tokio::select! {
+            biased;
+            event = self.events.recv() => match event {
+                Ok(event) => {
+                    let name = match event {
+                        ProgressEvent::Commit(_) => "commit",
+                        ProgressEvent::Record(_) => "record",
+                    };
+                    Some(Event::default().event(name).json_data(event))
+                }
+                Err(RecvError::Lagged(_)) => Some(self.status()),
+                // the job owns the sender, and is held here
+                Err(RecvError::Closed) => None,
+            },
+            changed = self.phase.changed() => match changed {
+                Ok(()) => Some(self.status()),
+                Err(_) => None,
+            },
+        }
+    }
+
+    fn status(&mut self) -> Result<Event, axum::Error> {
+        self.phase.borrow_and_update();
+        self.finished = self.job.is_finished();
+        Event::default()
+            .event("status")
+            .json_data(self.job.status())
+    }
+}
@@ -0,0 +1,77 @@
+//! Count stored records of each operation in the given repository.
+
+use axum::extract::{Query, State};
+use axum::{Form, Json};
+use serde::{Deserialize, Serialize};
+use snafu::OptionExt;
+use tracing::error;
+
+use crate::error::{FeedResult, MissingParameterSnafu};
+use crate::server::state::ServerState;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationCountQuery {
+    org: Option<String>,
+    repo: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationCount {
+    operation: String,
+    count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OperationCountResponse {
+    counts: Vec<OperationCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn operation_count(
+    State(state): State<ServerState>,
+    Query(query): Query<OperationCountQuery>,
+    Form(form): Form<OperationCountQuery>,
+) -> Json<OperationCountResponse> {
+    let result = operation_count_impl(state, query, form).await;
+    let response = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("Operation count error: {e}");
+            OperationCountResponse {
+                error: Some(e.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+
+    Json(response)
+}
+
+async fn operation_count_impl(
+    state: ServerState,
+    query: OperationCountQuery,
+    form: OperationCountQuery,
+) -> FeedResult<OperationCountResponse> {
+    let org = query
+        .org
+        .or(form.org)
+        .with_context(|| MissingParameterSnafu { param: "org" })?;
+    let repo = query
+        .repo
+        .or(form.repo)
+        .with_context(|| MissingParameterSnafu { param: "repo" })?;
+
+    let counts = state
+        .operation_counts(&org, &repo)
+        .await?
+        .into_iter()
+        .map(|(operation, count)| OperationCount { operation, count })
+        .collect();
+
+    Ok(OperationCountResponse {
+        counts,
+        error: None,
+    })
+}
@@ -1,31 +1,79 @@
+mod job;
+
+use std::sync::Arc;
+
 use gix_hash::ObjectId;
+use sha1_smol::Sha1;
 use snafu::{NoneError, OptionExt, ResultExt};
 use tokio::fs;
 use tokio::process::Command;
-use tracing::info;
+use tracing::{error, info, warn};
 
-use crate::conn::DbConn;
-use crate::consumer::DatabaseConsumer;
+pub use self::job::{Job, JobPhase, JobQueue, JobStatus, UpdateResult};
+use crate::config::FeedConfig;
+use crate::consumer::{write_rows, DatabaseConsumer, Progress, RowsWritten};
 use crate::error::{
     boxed, CloneRepoSnafu, ConvertObjectIdSnafu, FeedResult, FileSystemSnafu, GeneralSnafu,
-    InvalidNumberSnafu, InvalidUtf8Snafu, PullRepoSnafu, RunCommandSnafu,
+    InvalidNumberSnafu, InvalidUtf8Snafu, PullRepoSnafu, RevisionNotFoundSnafu, RunCommandSnafu,
 };
-use crate::local::{FetchRequest, FetchTask};
+use crate::local::{ErrorPolicy, FetchRequest, FetchSummary, FetchTask, TraversalMode};
+use crate::schema::{CursorKey, SnapshotTodo};
+use crate::storage::{self, Storage};
+
+/// Revision scanned when the request doesn't specify one
+pub const DEFAULT_REV: &str = "HEAD";
+
+/// Per request options of [ServerState::fetch_todo].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FetchOptions {
+    /// Fallback to server's default if not set
+    pub patterns: Option<Vec<String>>,
+    pub traversal: TraversalMode,
+    pub on_error: ErrorPolicy,
+    /// Fallback to server's default if not set
+    pub include: Option<Vec<String>>,
+    /// Fallback to server's default if not set
+    pub exclude: Option<Vec<String>>,
+}
 
 #[derive(Debug, Clone)]
 pub struct ServerState {
     repo_dir: String,
-    db: DbConn,
+    /// Default todo patterns, used when a request doesn't specify its own
+    patterns: Vec<String>,
+    context_lines: usize,
+    workers: usize,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    max_blob_size: usize,
+    alias_file: Option<String>,
+    insert_batch_size: usize,
+    storage: Arc<dyn Storage>,
+    jobs: Arc<JobQueue>,
 }
 
 impl ServerState {
-    pub async fn new(repo_dir: String) -> FeedResult<Self> {
+    pub async fn new(config: &FeedConfig) -> FeedResult<Self> {
+        let repo_dir = config.repo_dir.clone();
         fs::create_dir_all(&repo_dir)
             .await
             .context(FileSystemSnafu)?;
-        let db = DbConn::new().await?;
+        let storage = storage::connect(config).await?;
+        storage::migrate(storage.as_ref()).await?;
 
-        Ok(Self { repo_dir, db })
+        Ok(Self {
+            repo_dir,
+            patterns: config.patterns.clone(),
+            context_lines: config.context_lines,
+            workers: config.workers,
+            include: config.include.clone(),
+            exclude: config.exclude.clone(),
+            max_blob_size: config.max_blob_size,
+            alias_file: config.alias_file.clone(),
+            insert_batch_size: config.insert_batch_size,
+            storage,
+            jobs: Arc::new(JobQueue::new(config.max_jobs)),
+        })
     }
 
     pub async fn is_repo_exist(&self, org: &str, repo: &str) -> FeedResult<bool> {
@@ -38,6 +86,11 @@ impl ServerState {
         format!("{}-{}", org, repo)
     }
 
+    /// Web url of the repo
+    fn remote(&self, org: &str, repo: &str) -> String {
+        format!("https://github.com/{}/{}", org, repo)
+    }
+
     /// Get the repo path without checking if it exists
     pub fn repo_path(&self, org: &str, repo: &str) -> String {
         format!("{}/{}", self.repo_dir, self.repo_name(org, repo))
@@ -47,7 +100,7 @@ impl ServerState {
     pub async fn clone_repo(&self, org: &str, repo: &str) -> FeedResult<()> {
         let output = Command::new("git")
             .arg("clone")
-            .arg(format!("https://github.com/{}/{}.git", org, repo))
+            .arg(format!("{}.git", self.remote(org, repo)))
             .arg(self.repo_name(org, repo))
             .current_dir(&self.repo_dir)
             .output()
@@ -105,16 +158,56 @@ impl ServerState {
         Ok(output.stdout)
     }
 
-    pub async fn count_commits(&self, org: &str, repo: &str) -> FeedResult<usize> {
+    /// Resolve a branch, tag or commit to a commit hash. `None` if it doesn't
+    /// exist.
+    ///
+    /// Branches are looked up in `origin` first, as `git pull` only updates
+    /// the checked out one locally.
+    pub async fn resolve_rev(
+        &self,
+        org: &str,
+        repo: &str,
+        rev: &str,
+    ) -> FeedResult<Option<Vec<u8>>> {
+        let candidates = if rev == DEFAULT_REV {
+            vec![rev.to_string()]
+        } else {
+            vec![format!("origin/{rev}"), rev.to_string()]
+        };
+
+        for candidate in candidates {
+            let mut output = Command::new("git")
+                .arg("rev-parse")
+                .arg("--verify")
+                .arg("--quiet")
+                .arg(format!("{candidate}^{{commit}}"))
+                .current_dir(format!("{}/{}", self.repo_dir, self.repo_name(org, repo)))
+                .output()
+                .await
+                .with_context(|_| RunCommandSnafu {
+                    command: "git rev-parse --verify",
+                })?;
+
+            if output.status.success() {
+                // trim the tailing newline
+                output.stdout.pop();
+                return Ok(Some(output.stdout));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn count_commits(&self, org: &str, repo: &str, commit: &[u8]) -> FeedResult<usize> {
         let mut output = Command::new("git")
             .arg("rev-list")
             .arg("--count")
-            .arg("HEAD")
+            .arg(String::from_utf8_lossy(commit).as_ref())
             .current_dir(format!("{}/{}", self.repo_dir, self.repo_name(org, repo)))
             .output()
             .await
             .with_context(|_| RunCommandSnafu {
-                command: "git rev-list --count HEAD",
+                command: "git rev-list --count",
             })?;
 
         // trim the tailing newline
@@ -146,40 +239,271 @@ impl ServerState {
         String::from_utf8(output.stdout).context(InvalidUtf8Snafu)
     }
 
-    pub async fn fetch_todo(
+    /// Name of the ref to store on records. `HEAD` is named by the checked out
+    /// branch.
+    async fn ref_name(&self, org: &str, repo: &str, rev: &str) -> FeedResult<String> {
+        if rev == DEFAULT_REV {
+            self.current_branch(org, repo).await
+        } else {
+            Ok(rev.to_string())
+        }
+    }
+
+    /// Resolve a revision that must exist.
+    async fn resolve_existing_rev(&self, org: &str, repo: &str, rev: &str) -> FeedResult<String> {
+        let commit = self
+            .resolve_rev(org, repo, rev)
+            .await?
+            .with_context(|| RevisionNotFoundSnafu { rev })?;
+        String::from_utf8(commit).context(InvalidUtf8Snafu)
+    }
+
+    /// Last commit of `rev` fully ingested with `options`, if it is in the
+    /// checkout. A cursor lost to a force push starts the ingestion over.
+    pub async fn sync_cursor(
+        &self,
+        org: &str,
+        repo: &str,
+        rev: &str,
+        options: &FetchOptions,
+    ) -> FeedResult<Option<Vec<u8>>> {
+        let key = self.cursor_key(org, repo, rev, options).await?;
+        let stored = self.storage.sync_cursor(&key).await?;
+        match stored {
+            Some(commit) => self.resolve_rev(org, repo, &commit).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Key of the sync cursor of walks of `rev` with `options`. The options
+    /// are hashed with the server's defaults filled in.
+    async fn cursor_key(
+        &self,
+        org: &str,
+        repo: &str,
+        rev: &str,
+        options: &FetchOptions,
+    ) -> FeedResult<CursorKey> {
+        // keys of a json object are sorted, so equal options hash the same
+        let scanned = serde_json::json!({
+            "patterns": options.patterns.as_ref().unwrap_or(&self.patterns),
+            // repo markers only apply if the request gives no patterns
+            "patterns_requested": options.patterns.is_some(),
+            "include": options.include.as_ref().unwrap_or(&self.include),
+            "exclude": options.exclude.as_ref().unwrap_or(&self.exclude),
+            "max_blob_size": self.max_blob_size,
+        });
+        Ok(CursorKey {
+            repo_name: self.repo_name(org, repo),
+            ref_name: self.ref_name(org, repo, rev).await?,
+            traversal: options.traversal.as_str().to_string(),
+            options_hash: Sha1::from(scanned.to_string()).digest().to_string(),
+        })
+    }
+
+    /// Number of stored records of each operation.
+    pub async fn operation_counts(&self, org: &str, repo: &str) -> FeedResult<Vec<(String, i64)>> {
+        self.storage
+            .operation_counts(&self.repo_name(org, repo))
+            .await
+    }
+
+    /// Queue an update of the repo, unless the same one is already queued or
+    /// running. Returns the job and whether it is new.
+    pub fn submit_update(
         &self,
         org: &str,
         repo: &str,
+        rev: &str,
+        options: FetchOptions,
+    ) -> (Arc<Job>, bool) {
+        let (job, created) = self.jobs.submit(org, repo, rev, options);
+        if created {
+            let state = self.clone();
+            let job = job.clone();
+            tokio::spawn(async move {
+                // the checkout comes first, so waiting on it takes no permit
+                let _checkout = state.jobs.lock_checkout(&job).await;
+                let _permit = state.jobs.acquire().await;
+                let result = state.update_repo(&job).await;
+                if let Err(e) = &result {
+                    error!(
+                        "Update job {} of {}/{} failed: {e}",
+                        job.id, job.org, job.repo
+                    );
+                }
+                state.jobs.finish(&job, result);
+            });
+        }
+        (job, created)
+    }
+
+    pub fn job(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.get(id)
+    }
+
+    /// Clone or pull the repo of `job`, then ingest the commits of its ref
+    /// since the sync cursor.
+    async fn update_repo(&self, job: &Job) -> FeedResult<UpdateResult> {
+        let (org, repo, rev) = (job.org.as_str(), job.repo.as_str(), job.rev.as_str());
+
+        let repo_exist = self.is_repo_exist(org, repo).await?;
+        if repo_exist {
+            job.set_phase(JobPhase::Pulling);
+            self.pull_repo(org, repo).await?;
+        } else {
+            job.set_phase(JobPhase::Cloning);
+            self.clone_repo(org, repo).await?;
+        }
+
+        // resume from the last fully ingested commit, not the checkout, which
+        // may be ahead of what is stored after a failed run
+        let since = self.sync_cursor(org, repo, rev, &job.options).await?;
+        let num_ingested_commit = match &since {
+            Some(commit) => self.count_commits(org, repo, commit).await?,
+            None => 0,
+        };
+        let num_curr_commit = match self.resolve_rev(org, repo, rev).await? {
+            Some(commit) => self.count_commits(org, repo, &commit).await?,
+            None => 0,
+        };
+        let num_new_commit = num_curr_commit.saturating_sub(num_ingested_commit);
+
+        job.set_phase(JobPhase::Walking);
+        let (summary, written) = self
+            .fetch_todo(
+                org,
+                repo,
+                rev,
+                since,
+                job.options.clone(),
+                job.progress.clone(),
+            )
+            .await?;
+
+        Ok(UpdateResult {
+            repo_exist,
+            ref_name: self.ref_name(org, repo, rev).await?,
+            num_new_commit: num_new_commit as u64,
+            num_todo_changes: written.records as u64,
+            num_lifecycles: written.lifecycles as u64,
+            skipped_commits: summary.skipped,
+            truncated: summary.truncated,
+        })
+    }
+
+    /// Ingest the history of `rev` down to `since`. The sync cursor moves to
+    /// `rev` only once all of its rows are written, so an interrupted run is
+    /// picked up again from the old cursor. Skipped commits and a walk cut
+    /// short don't hold it back, as they would fail again on every run.
+    async fn fetch_todo(
+        &self,
+        org: &str,
+        repo: &str,
+        rev: &str,
         since: Option<Vec<u8>>,
-    ) -> FeedResult<()> {
+        options: FetchOptions,
+        progress: Arc<Progress>,
+    ) -> FeedResult<(FetchSummary, RowsWritten)> {
         let since = if let Some(since) = since {
             Some(ObjectId::from_hex(&since).context(ConvertObjectIdSnafu)?)
         } else {
             None
         };
 
-        let consumer = DatabaseConsumer::new();
+        let commit = self.resolve_existing_rev(org, repo, rev).await?;
+        let ref_name = self.ref_name(org, repo, rev).await?;
+        let cursor_key = self.cursor_key(org, repo, rev, &options).await?;
         let fetch_request = FetchRequest {
             root: self.repo_path(org, repo),
-            branch: self.current_branch(org, repo).await?,
+            rev: commit.clone(),
+            ref_name: ref_name.clone(),
             since,
             repo: self.repo_name(org, repo),
+            patterns_requested: options.patterns.is_some(),
+            patterns: options.patterns.unwrap_or_else(|| self.patterns.clone()),
+            context_lines: self.context_lines,
+            remote: Some(self.remote(org, repo)),
+            traversal: options.traversal,
+            workers: self.workers,
+            on_error: options.on_error,
+            include: options.include.unwrap_or_else(|| self.include.clone()),
+            exclude: options.exclude.unwrap_or_else(|| self.exclude.clone()),
+            max_blob_size: self.max_blob_size,
+            alias_file: self.alias_file.clone(),
         };
-        FetchTask::new(fetch_request)
-            .map_err(boxed)
-            .context(GeneralSnafu)?
-            .execute(&consumer)
+        let task = FetchTask::new(fetch_request)
             .map_err(boxed)
             .context(GeneralSnafu)?;
 
-        let insert_request = consumer.into_insert();
-        println!("{insert_request}");
-        self.db
-            .execute(&insert_request)
+        let stored_open = self
+            .storage
+            .open_lifecycles(&self.repo_name(org, repo), &ref_name)
+            .await?;
+
+        // rows are written while the walk is running on a blocking thread
+        let (consumer, receiver) = DatabaseConsumer::new(self.insert_batch_size, progress);
+        let walk = tokio::task::spawn_blocking(move || task.execute(&consumer, stored_open));
+        let written = write_rows(self.storage.as_ref(), receiver, self.insert_batch_size).await;
+        let summary = walk
             .await
             .map_err(boxed)
+            .context(GeneralSnafu)?
+            .map_err(boxed)
             .context(GeneralSnafu)?;
+        let written = written.map_err(boxed).context(GeneralSnafu)?;
+
+        // walking them again fails the same way, so the cursor moves past
+        // them and they are reported in the result instead
+        if !summary.skipped.is_empty() || summary.truncated.is_some() {
+            warn!(
+                "Moving the sync cursor of {org}/{repo} {ref_name} past {} skipped commits, \
+                 truncated: {:?}",
+                summary.skipped.len(),
+                summary.truncated
+            );
+        }
+        self.storage.set_sync_cursor(&cursor_key, &commit).await?;
+
+        Ok((summary, written))
+    }
 
-        Ok(())
+    /// List todos present at the given revision, optionally with the commit
+    /// that introduced each of them.
+    pub async fn snapshot(
+        &self,
+        org: &str,
+        repo: &str,
+        rev: &str,
+        blame: bool,
+    ) -> FeedResult<Vec<SnapshotTodo>> {
+        let fetch_request = FetchRequest {
+            root: self.repo_path(org, repo),
+            rev: self.resolve_existing_rev(org, repo, rev).await?,
+            ref_name: self.ref_name(org, repo, rev).await?,
+            since: None,
+            repo: self.repo_name(org, repo),
+            patterns: self.patterns.clone(),
+            patterns_requested: false,
+            context_lines: self.context_lines,
+            remote: Some(self.remote(org, repo)),
+            traversal: TraversalMode::default(),
+            workers: self.workers,
+            on_error: ErrorPolicy::default(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            max_blob_size: self.max_blob_size,
+            alias_file: self.alias_file.clone(),
+        };
+        let task = FetchTask::new(fetch_request)
+            .map_err(boxed)
+            .context(GeneralSnafu)?;
+        // blame walks the history, so it can take a while
+        tokio::task::spawn_blocking(move || task.snapshot(blame))
+            .await
+            .map_err(boxed)
+            .context(GeneralSnafu)?
+            .map_err(boxed)
+            .context(GeneralSnafu)
     }
 }
@@ -0,0 +1,262 @@
+//! Update jobs, run in the background so requests return right away.
+//!
+//! Asking for the same update as a queued or running job returns that job
+//! instead of starting another. Updates of one repo with another ref or
+//! options are jobs of their own, and run one after another as they share
+//! the checkout.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, watch, OwnedMutexGuard, Semaphore, SemaphorePermit};
+
+use super::FetchOptions;
+use crate::consumer::{Progress, ProgressEvent};
+use crate::error::FeedResult;
+use crate::local::SkippedCommit;
+
+/// Finished jobs kept for polling. The oldest are dropped beyond this.
+const MAX_FINISHED_JOBS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPhase {
+    /// Waiting for other jobs to finish
+    Queued,
+    Cloning,
+    Pulling,
+    /// Walking the history and writing rows
+    Walking,
+    Done,
+    Failed,
+}
+
+/// Outcome of a finished update.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpdateResult {
+    pub repo_exist: bool,
+    /// Ref the rows are stored under, the current branch for `HEAD`
+    pub ref_name: String,
+    pub num_new_commit: u64,
+    pub num_todo_changes: u64,
+    pub num_lifecycles: u64,
+    /// Commits that couldn't be diffed. The sync cursor moves past them, so
+    /// later updates don't try them again
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_commits: Vec<SkippedCommit>,
+    /// Why the history walk stopped early, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: u64,
+    pub org: String,
+    pub repo: String,
+    pub rev: String,
+    pub options: FetchOptions,
+    pub progress: Arc<Progress>,
+    /// Watched by event streams to report phase changes
+    phase: watch::Sender<JobPhase>,
+    state: Mutex<JobState>,
+}
+
+#[derive(Debug)]
+struct JobState {
+    result: Option<UpdateResult>,
+    error: Option<String>,
+}
+
+/// A job as reported by `/api/jobs/{id}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: u64,
+    pub org: String,
+    pub repo: String,
+    #[serde(rename = "ref")]
+    pub rev: String,
+    pub phase: JobPhase,
+    pub commits_walked: usize,
+    /// 0 until the commits to walk are known
+    pub commits_total: usize,
+    pub records_found: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<UpdateResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub fn set_phase(&self, phase: JobPhase) {
+        self.phase.send_replace(phase);
+    }
+
+    pub fn status(&self) -> JobStatus {
+        let state = self.state.lock().unwrap();
+        JobStatus {
+            id: self.id,
+            org: self.org.clone(),
+            repo: self.repo.clone(),
+            rev: self.rev.clone(),
+            phase: *self.phase.borrow(),
+            commits_walked: self.progress.commits_walked.load(Ordering::Relaxed),
+            commits_total: self.progress.commits_total.load(Ordering::Relaxed),
+            records_found: self.progress.records_found.load(Ordering::Relaxed),
+            result: state.result.clone(),
+            error: state.error.clone(),
+        }
+    }
+
+    fn key(&self) -> JobKey {
+        (
+            self.org.clone(),
+            self.repo.clone(),
+            self.rev.clone(),
+            self.options.clone(),
+        )
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(*self.phase.borrow(), JobPhase::Done | JobPhase::Failed)
+    }
+
+    /// Listen to the phase changes and walk events of the job from now on.
+    pub fn subscribe(
+        &self,
+    ) -> (
+        watch::Receiver<JobPhase>,
+        broadcast::Receiver<ProgressEvent>,
+    ) {
+        (self.phase.subscribe(), self.progress.events.subscribe())
+    }
+}
+
+#[derive(Debug)]
+pub struct JobQueue {
+    next_id: AtomicU64,
+    jobs: Mutex<Jobs>,
+    /// One permit per job allowed to run at a time
+    permits: Semaphore,
+    /// Held by the job running on each `(org, repo)` checkout
+    checkouts: Mutex<HashMap<(String, String), Arc<tokio::sync::Mutex<()>>>>,
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    /// By id, which grows with submission order
+    all: BTreeMap<u64, Arc<Job>>,
+    /// Queued or running job of each update
+    active: HashMap<JobKey, u64>,
+}
+
+/// What makes two updates the same: `(org, repo, rev, options)`.
+type JobKey = (String, String, String, FetchOptions);
+
+impl JobQueue {
+    pub fn new(max_jobs: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            jobs: Mutex::new(Jobs::default()),
+            permits: Semaphore::new(max_jobs.max(1)),
+            checkouts: Mutex::default(),
+        }
+    }
+
+    /// Add a queued job for the update, or get the one already queued or
+    /// running with the same parameters. The flag tells whether the job is
+    /// new, and so has to be started by the caller.
+    pub fn submit(
+        &self,
+        org: &str,
+        repo: &str,
+        rev: &str,
+        options: FetchOptions,
+    ) -> (Arc<Job>, bool) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let key = (
+            org.to_string(),
+            repo.to_string(),
+            rev.to_string(),
+            options.clone(),
+        );
+        if let Some(job) = jobs.active.get(&key).and_then(|id| jobs.all.get(id)) {
+            return (job.clone(), false);
+        }
+
+        let job = Arc::new(Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            org: org.to_string(),
+            repo: repo.to_string(),
+            rev: rev.to_string(),
+            options,
+            progress: Arc::default(),
+            phase: watch::channel(JobPhase::Queued).0,
+            state: Mutex::new(JobState {
+                result: None,
+                error: None,
+            }),
+        });
+        jobs.active.insert(key, job.id);
+        jobs.all.insert(job.id, job.clone());
+        (job, true)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().all.get(&id).cloned()
+    }
+
+    /// Wait for a turn to run. The job runs while the permit is held.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        // the semaphore is never closed
+        self.permits.acquire().await.unwrap()
+    }
+
+    /// Wait until no other job uses the checkout of `job`'s repo, and keep
+    /// others off it while the guard is held.
+    pub async fn lock_checkout(&self, job: &Job) -> OwnedMutexGuard<()> {
+        let checkout = self
+            .checkouts
+            .lock()
+            .unwrap()
+            .entry((job.org.clone(), job.repo.clone()))
+            .or_default()
+            .clone();
+        checkout.lock_owned().await
+    }
+
+    /// Store the outcome of a job and let the repo take new jobs.
+    pub fn finish(&self, job: &Job, result: FeedResult<UpdateResult>) {
+        // the outcome is stored before the phase changes, so whoever sees the
+        // job finished sees it too
+        let phase = {
+            let mut state = job.state.lock().unwrap();
+            match result {
+                Ok(result) => {
+                    state.result = Some(result);
+                    JobPhase::Done
+                }
+                Err(e) => {
+                    state.error = Some(e.to_string());
+                    JobPhase::Failed
+                }
+            }
+        };
+        job.set_phase(phase);
+
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.active.remove(&job.key());
+        let finished = jobs
+            .all
+            .values()
+            .filter(|job| job.is_finished())
+            .map(|job| job.id)
+            .collect::<Vec<_>>();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_JOBS);
+        for id in &finished[..excess] {
+            jobs.all.remove(id);
+        }
+    }
+}
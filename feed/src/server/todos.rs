@@ -0,0 +1,81 @@
+//! List todos present at a revision of the given repository.
+
+use axum::extract::{Query, State};
+use axum::{Form, Json};
+use serde::{Deserialize, Serialize};
+use snafu::OptionExt;
+use tracing::error;
+
+use crate::error::{FeedResult, MissingParameterSnafu};
+use crate::schema::SnapshotTodo;
+use crate::server::state::{ServerState, DEFAULT_REV};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TodosQuery {
+    org: Option<String>,
+    repo: Option<String>,
+    /// Branch, tag or commit. Default to `HEAD`
+    rev: Option<String>,
+    /// Attribute each todo to the commit that introduced it
+    blame: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct TodosResponse {
+    rev: String,
+    num_todos: usize,
+    todos: Vec<SnapshotTodo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn todos(
+    State(state): State<ServerState>,
+    Query(query): Query<TodosQuery>,
+    Form(form): Form<TodosQuery>,
+) -> Json<TodosResponse> {
+    let result = todos_impl(state, query, form).await;
+    let response = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("Todos error: {e}");
+            TodosResponse {
+                error: Some(e.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+
+    Json(response)
+}
+
+async fn todos_impl(
+    state: ServerState,
+    query: TodosQuery,
+    form: TodosQuery,
+) -> FeedResult<TodosResponse> {
+    let org = query
+        .org
+        .or(form.org)
+        .with_context(|| MissingParameterSnafu { param: "org" })?;
+    let repo = query
+        .repo
+        .or(form.repo)
+        .with_context(|| MissingParameterSnafu { param: "repo" })?;
+    let rev = query
+        .rev
+        .or(form.rev)
+        .unwrap_or_else(|| DEFAULT_REV.to_string());
+
+    let blame = query.blame.or(form.blame).unwrap_or(false);
+
+    let todos = state.snapshot(&org, &repo, &rev, blame).await?;
+
+    Ok(TodosResponse {
+        rev,
+        num_todos: todos.len(),
+        todos,
+        error: None,
+    })
+}
@@ -1,23 +1,42 @@
 use axum::extract::{Query, State};
 use axum::{Form, Json};
 use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, ResultExt};
+use snafu::OptionExt;
 use tracing::error;
 
-use crate::error::{boxed, FeedResult, GeneralSnafu, MissingParameterSnafu};
-use crate::server::state::ServerState;
+use crate::error::{FeedResult, MissingParameterSnafu};
+use crate::local::{ErrorPolicy, TraversalMode};
+use crate::server::state::{FetchOptions, ServerState, DEFAULT_REV};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateRepoQuery {
     org: Option<String>,
     repo: Option<String>,
+    /// Newline separated todo patterns. Fallback to server's default if not set.
+    patterns: Option<String>,
+    /// `first_parent` (default) or `all`
+    traversal: Option<TraversalMode>,
+    /// `skip` (default) or `abort` on commits that can't be read or diffed
+    on_error: Option<ErrorPolicy>,
+    /// Newline separated globs of paths to scan. Fallback to server's default
+    /// if not set.
+    include: Option<String>,
+    /// Newline separated globs of paths not to scan. Fallback to server's
+    /// default if not set.
+    exclude: Option<String>,
+    /// Branch, tag or commit to scan. Default to `HEAD`
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
 }
 
+/// The update runs in the background. Poll `/api/jobs/{job_id}` for its
+/// progress and outcome.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct UpdateRepoResponse {
-    repo_exist: bool,
-    num_new_commit: u64,
-    num_todo_changes: u64,
+    job_id: u64,
+    /// Whether the same update was already queued or running, as the
+    /// returned job
+    existing: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
 }
@@ -28,7 +47,7 @@ pub async fn update_repo(
     Query(query): Query<UpdateRepoQuery>,
     Form(form): Form<UpdateRepoQuery>,
 ) -> Json<UpdateRepoResponse> {
-    let result = update_repo_impl(state, query, form).await;
+    let result = update_repo_impl(state, query, form);
     let response = match result {
         Ok(resp) => resp,
         Err(e) => {
@@ -43,7 +62,7 @@ pub async fn update_repo(
     Json(response)
 }
 
-async fn update_repo_impl(
+fn update_repo_impl(
     state: ServerState,
     query: UpdateRepoQuery,
     form: UpdateRepoQuery,
@@ -56,41 +75,32 @@ async fn update_repo_impl(
         .repo
         .or(form.repo)
         .with_context(|| MissingParameterSnafu { param: "repo" })?;
-
-    let mut curr_head = vec![];
-    #[allow(unused_assignments)]
-    let mut num_new_commit = 0;
-
-    // clone or pull repo
-    if state.is_repo_exist(&org, &repo).await? {
-        curr_head = state.head_commit(&org, &repo).await?;
-        let num_prev_commit = state.count_commits(&org, &repo).await?;
-        state.pull_repo(&org, &repo).await?;
-        let num_curr_commit = state.count_commits(&org, &repo).await?;
-        num_new_commit = num_curr_commit - num_prev_commit;
-    } else {
-        state.clone_repo(&org, &repo).await?;
-        num_new_commit = state.count_commits(&org, &repo).await?;
-    }
-
-    let repo_exist = !curr_head.is_empty();
-    let curr_head = if curr_head.is_empty() {
-        None
-    } else {
-        Some(curr_head)
+    let options = FetchOptions {
+        patterns: query.patterns.or(form.patterns).map(split_lines),
+        traversal: query.traversal.or(form.traversal).unwrap_or_default(),
+        on_error: query.on_error.or(form.on_error).unwrap_or_default(),
+        include: query.include.or(form.include).map(split_lines),
+        exclude: query.exclude.or(form.exclude).map(split_lines),
     };
+    let rev = query
+        .git_ref
+        .or(form.git_ref)
+        .unwrap_or_else(|| DEFAULT_REV.to_string());
 
-    state
-        .fetch_todo(&org, &repo, curr_head)
-        .await
-        .map_err(boxed)
-        .context(GeneralSnafu)?;
+    let (job, created) = state.submit_update(&org, &repo, &rev, options);
 
     Ok(UpdateRepoResponse {
-        repo_exist,
-        num_new_commit: num_new_commit as u64,
-        // todo: count changes
-        num_todo_changes: 0,
+        job_id: job.id,
+        existing: !created,
         error: None,
     })
 }
+
+/// Split a newline separated parameter, dropping empty lines.
+fn split_lines(param: String) -> Vec<String> {
+    param
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,130 @@
+//! Where rows are written and read back from.
+//!
+//! [Storage] is implemented for the GreptimeDB HTTP API, MySQL and Postgres
+//! through sqlx, and an embedded SQLite file. `--storage` picks one.
+
+mod greptime;
+mod migration;
+mod sql;
+mod sqlite;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use snafu::OptionExt;
+use tracing::info;
+
+use self::greptime::GreptimeStorage;
+pub use self::migration::migrate;
+use self::sql::SqlStorage;
+use self::sqlite::SqliteStorage;
+use crate::config::FeedConfig;
+use crate::error::{FeedResult, MissingDatabaseUrlSnafu};
+use crate::schema::{CursorKey, OpenLifecycle, Table, Value};
+
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    /// SQL dialect tables are created in.
+    fn dialect(&self) -> Dialect;
+
+    /// Execute a statement that returns no rows, like `CREATE TABLE`.
+    async fn execute(&self, sql: &str) -> FeedResult<()>;
+
+    /// Execute statements that return no rows in one transaction, where the
+    /// database can roll back schema changes. Elsewhere they are executed in
+    /// order, and those before a failed one stay applied.
+    async fn execute_all(&self, statements: &[String]) -> FeedResult<()> {
+        for statement in statements {
+            self.execute(statement).await?;
+        }
+        Ok(())
+    }
+
+    /// Names of the tables in the database.
+    async fn tables(&self) -> FeedResult<Vec<String>>;
+
+    /// Insert rows into `table`. Each row has a value for every column of the
+    /// table, in order. Rows whose primary key is already stored are skipped,
+    /// so ingesting a commit again writes nothing new. Returns the number of
+    /// rows written.
+    async fn insert(&self, table: &Table, rows: Vec<Vec<Value>>) -> FeedResult<u64>;
+
+    /// Version of the latest applied migration, 0 before any. The
+    /// `schema_migrations` table must exist.
+    async fn schema_version(&self) -> FeedResult<i64>;
+
+    /// Last commit fully ingested by walks of `key`, or `None` if there was
+    /// none.
+    async fn sync_cursor(&self, key: &CursorKey) -> FeedResult<Option<String>>;
+
+    /// Record `commit` as fully ingested, replacing the previous cursor.
+    async fn set_sync_cursor(&self, key: &CursorKey, commit: &str) -> FeedResult<()>;
+
+    /// Lifecycles of `ref_name` in `repo` whose todo is introduced but not
+    /// resolved yet.
+    async fn open_lifecycles(&self, repo: &str, ref_name: &str) -> FeedResult<Vec<OpenLifecycle>>;
+
+    /// Delete the rows of open lifecycles, once their resolved ones are
+    /// written.
+    async fn delete_open_lifecycles(&self, lifecycles: &[OpenLifecycle]) -> FeedResult<()>;
+
+    /// Number of records of each operation in `repo`, ordered by operation.
+    async fn operation_counts(&self, repo: &str) -> FeedResult<Vec<(String, i64)>>;
+}
+
+/// Databases differ in the column types and keys they take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Greptime,
+    MySql,
+    Postgres,
+    Sqlite,
+}
+
+impl Dialect {
+    /// Start and end of an insert statement that skips rows whose primary
+    /// key is taken. GreptimeDB has no such clause, but replaces rows with
+    /// the same key and time index, which is as good for identical rows.
+    fn insert_ignore(self) -> (&'static str, &'static str) {
+        match self {
+            Dialect::Greptime => ("INSERT INTO", ""),
+            Dialect::MySql => ("INSERT IGNORE INTO", ""),
+            Dialect::Postgres => ("INSERT INTO", " ON CONFLICT DO NOTHING"),
+            Dialect::Sqlite => ("INSERT OR IGNORE INTO", ""),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StorageKind {
+    /// GreptimeDB through its HTTP API at `--greptime-url`
+    Greptime,
+    /// MySQL or Postgres at the url in environment variable `DATABASE_URL`
+    Sql,
+    /// SQLite file at `--sqlite-path`
+    Sqlite,
+}
+
+/// Connect to the storage selected by `config`.
+pub async fn connect(config: &FeedConfig) -> FeedResult<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match config.storage {
+        StorageKind::Greptime => Arc::new(GreptimeStorage::new(
+            &config.greptime_url,
+            &config.greptime_db,
+        )),
+        StorageKind::Sql => {
+            // read from the environment so the password isn't logged with
+            // the config
+            let url = std::env::var("DATABASE_URL")
+                .ok()
+                .context(MissingDatabaseUrlSnafu)?;
+            Arc::new(SqlStorage::connect(&url).await?)
+        }
+        StorageKind::Sqlite => Arc::new(SqliteStorage::connect(&config.sqlite_path).await?),
+    };
+    info!("Connected to {:?} storage", config.storage);
+
+    Ok(storage)
+}
@@ -0,0 +1,333 @@
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use super::{Dialect, Storage};
+use crate::error::{DecodeResponseSnafu, FeedResult, GreptimeRequestSnafu, HttpRequestSnafu};
+use crate::schema::{CursorKey, LifecycleEvent, OpenLifecycle, Table, Value, SYNC_CURSORS};
+
+/// SQL API of GreptimeDB over HTTP. Statements are sent as text, so values
+/// are written as literals.
+#[derive(Debug, Clone)]
+pub struct GreptimeStorage {
+    client: reqwest::Client,
+    /// Like `http://localhost:4000/v1/sql`
+    url: String,
+    db: String,
+}
+
+/// Body of a GreptimeDB HTTP API response. Older versions report errors with
+/// a non-zero `code` on a successful status.
+#[derive(Debug, Deserialize)]
+struct GreptimeResponse {
+    #[serde(default)]
+    code: u32,
+    error: Option<String>,
+    /// One per statement
+    #[serde(default)]
+    output: Vec<GreptimeOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GreptimeOutput {
+    /// Only set for queries
+    records: Option<GreptimeRecords>,
+    /// Only set for statements that write
+    affectedrows: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GreptimeRecords {
+    rows: Vec<serde_json::Value>,
+}
+
+impl GreptimeStorage {
+    pub fn new(url: &str, db: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: format!("{}/v1/sql", url.trim_end_matches('/')),
+            db: db.to_string(),
+        }
+    }
+
+    /// Execute one statement and return its output, if there is any.
+    async fn send(&self, sql: String) -> FeedResult<Option<GreptimeOutput>> {
+        let response = self
+            .client
+            .post(&self.url)
+            .query(&[("db", &self.db)])
+            .form(&[("sql", sql)])
+            .send()
+            .await
+            .context(HttpRequestSnafu)?;
+        let status = response.status();
+        let body = response.text().await.context(HttpRequestSnafu)?;
+
+        let result = match serde_json::from_str::<GreptimeResponse>(&body) {
+            Ok(result) if status.is_success() && result.code == 0 => result,
+            Ok(result) => {
+                return GreptimeRequestSnafu {
+                    status: status.as_u16(),
+                    error: result.error.unwrap_or(body),
+                }
+                .fail();
+            }
+            Err(_) if status.is_success() => return Ok(None),
+            Err(_) => {
+                return GreptimeRequestSnafu {
+                    status: status.as_u16(),
+                    error: body,
+                }
+                .fail();
+            }
+        };
+
+        Ok(result.output.into_iter().next())
+    }
+
+    /// Execute one statement. Rows of its result are decoded to `T`, each
+    /// from an array of its column values.
+    async fn sql<T: DeserializeOwned>(&self, sql: String) -> FeedResult<Vec<T>> {
+        let rows = self
+            .send(sql)
+            .await?
+            .and_then(|output| output.records)
+            .map(|records| records.rows)
+            .unwrap_or_default();
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row).context(DecodeResponseSnafu))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Storage for GreptimeStorage {
+    fn dialect(&self) -> Dialect {
+        Dialect::Greptime
+    }
+
+    async fn execute(&self, sql: &str) -> FeedResult<()> {
+        self.sql::<serde_json::Value>(sql.to_string()).await?;
+        Ok(())
+    }
+
+    async fn tables(&self) -> FeedResult<Vec<String>> {
+        let rows = self.sql::<(String,)>("SHOW TABLES;".to_string()).await?;
+        Ok(rows.into_iter().map(|(table,)| table).collect())
+    }
+
+    /// Rows replacing one with the same key and time index are counted too.
+    async fn insert(&self, table: &Table, rows: Vec<Vec<Value>>) -> FeedResult<u64> {
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let values = row.iter().map(literal).collect::<Vec<_>>();
+                format!("({})", values.join(","))
+            })
+            .collect::<Vec<_>>();
+        // rows with the same key and time index replace each other
+        let sql = format!(
+            "INSERT INTO `{}` (`{}`) VALUES {};",
+            table.name,
+            table.columns.join("`, `"),
+            rows.join(",")
+        );
+        let output = self.send(sql).await?;
+        Ok(output
+            .and_then(|output| output.affectedrows)
+            .unwrap_or_default())
+    }
+
+    async fn schema_version(&self) -> FeedResult<i64> {
+        let sql = "SELECT MAX(version) FROM schema_migrations;".to_string();
+        let rows = self.sql::<(Option<i64>,)>(sql).await?;
+        Ok(rows
+            .into_iter()
+            .next()
+            .and_then(|(version,)| version)
+            .unwrap_or_default())
+    }
+
+    async fn sync_cursor(&self, key: &CursorKey) -> FeedResult<Option<String>> {
+        let sql = format!(
+            "SELECT commit_id FROM sync_cursors WHERE repo_name = {} AND ref_name = {} \
+             AND traversal = {} AND options_hash = {} ORDER BY synced_at DESC LIMIT 1;",
+            text_literal(&key.repo_name),
+            text_literal(&key.ref_name),
+            text_literal(&key.traversal),
+            text_literal(&key.options_hash),
+        );
+        let rows = self.sql::<(String,)>(sql).await?;
+        Ok(rows.into_iter().next().map(|(commit_id,)| commit_id))
+    }
+
+    /// Each update is a new row, as `synced_at` is the time index.
+    async fn set_sync_cursor(&self, key: &CursorKey, commit: &str) -> FeedResult<()> {
+        let row = vec![
+            Value::Text(key.repo_name.clone()),
+            Value::Text(key.ref_name.clone()),
+            Value::Text(key.traversal.clone()),
+            Value::Text(key.options_hash.clone()),
+            Value::Text(commit.to_string()),
+        ];
+        self.insert(&SYNC_CURSORS, vec![row]).await?;
+        Ok(())
+    }
+
+    /// Timestamps come back as milliseconds since the epoch.
+    async fn open_lifecycles(&self, repo: &str, ref_name: &str) -> FeedResult<Vec<OpenLifecycle>> {
+        let sql = format!(
+            "SELECT file_path, content, introduced_commit, introduced_time, introducer, \
+             latest_file_path, latest_content FROM lifecycles \
+             WHERE repo_name = {} AND ref_name = {} \
+             AND introduced_commit <> '' AND resolved_commit = '';",
+            text_literal(repo),
+            text_literal(ref_name),
+        );
+        let rows = self
+            .sql::<(String, String, String, i64, String, String, String)>(sql)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let (file_path, content, commit_id, millis, author_name, latest_path, latest) = row;
+                let commit_time = Utc.timestamp_millis_opt(millis).single()?;
+                Some(OpenLifecycle {
+                    repo_name: repo.to_string(),
+                    ref_name: ref_name.to_string(),
+                    file_path,
+                    content,
+                    introduced: LifecycleEvent {
+                        commit_id,
+                        commit_time: commit_time.into(),
+                        author_name,
+                    },
+                    latest_file_path: latest_path,
+                    latest_content: latest,
+                })
+            })
+            .collect())
+    }
+
+    async fn delete_open_lifecycles(&self, lifecycles: &[OpenLifecycle]) -> FeedResult<()> {
+        for lifecycle in lifecycles {
+            let sql = format!(
+                "DELETE FROM lifecycles WHERE repo_name = {} AND ref_name = {} \
+                 AND file_path = {} AND content = {} AND introduced_commit = {} \
+                 AND resolved_commit = '';",
+                text_literal(&lifecycle.repo_name),
+                text_literal(&lifecycle.ref_name),
+                text_literal(&lifecycle.file_path),
+                text_literal(&lifecycle.content),
+                text_literal(&lifecycle.introduced.commit_id),
+            );
+            self.execute(&sql).await?;
+        }
+        Ok(())
+    }
+
+    async fn operation_counts(&self, repo: &str) -> FeedResult<Vec<(String, i64)>> {
+        let sql = format!(
+            "SELECT operation, COUNT(*) FROM records WHERE repo_name = {} \
+             GROUP BY operation ORDER BY operation;",
+            text_literal(repo),
+        );
+        self.sql(sql).await
+    }
+}
+
+/// SQL literal of a value.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Text(text) => text_literal(text),
+        Value::Int(Some(int)) => int.to_string(),
+        Value::Time(Some(time)) => format!("'{}'", time.format("%Y-%m-%d %H:%M:%S")),
+        Value::Int(None) | Value::Time(None) => "NULL".to_string(),
+    }
+}
+
+/// Single quotes are doubled, the only escape in standard SQL. Backslashes
+/// are not escape characters in GreptimeDB and are kept as is, so it reads
+/// the text back byte for byte, as `tests::text_round_trips` checks.
+fn text_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRICKY_TEXT: &str = "it's \"quoted\" \\n C:\\path\\ ''\nnext\r\nline\t\\";
+
+    #[test]
+    fn only_single_quotes_are_escaped() {
+        assert_eq!(text_literal("it's"), "'it''s'");
+        assert_eq!(text_literal("''"), "''''''");
+        assert_eq!(text_literal("say \"hi\""), "'say \"hi\"'");
+        assert_eq!(text_literal("C:\\dir\\"), "'C:\\dir\\'");
+        assert_eq!(text_literal("a\nb"), "'a\nb'");
+    }
+
+    /// Runs against the GreptimeDB at `GREPTIME_URL`, like
+    /// `GREPTIME_URL=http://localhost:4000 cargo test text_round_trips`, and
+    /// passes without checking anything if it isn't set.
+    #[tokio::test]
+    async fn text_round_trips() {
+        let Ok(url) = std::env::var("GREPTIME_URL") else {
+            eprintln!("GREPTIME_URL is not set, not checking text round trips");
+            return;
+        };
+        let storage = GreptimeStorage::new(&url, "public");
+        let table = Table {
+            name: "text_round_trip",
+            columns: &["id", "content", "ts"],
+        };
+        storage
+            .execute(
+                "CREATE TABLE IF NOT EXISTS text_round_trip (id STRING, content STRING, \
+                 ts TIMESTAMP, TIME INDEX (ts), PRIMARY KEY (id));",
+            )
+            .await
+            .unwrap();
+
+        let texts = [
+            TRICKY_TEXT,
+            "'",
+            "\\",
+            "\\'",
+            "\"",
+            "\n",
+            "",
+            "todo: 修复 ✓",
+        ];
+        let time =
+            chrono::NaiveDate::from_ymd_opt(2023, 1, 1).and_then(|date| date.and_hms_opt(0, 0, 0));
+        let rows = texts
+            .iter()
+            .enumerate()
+            .map(|(id, text)| {
+                vec![
+                    Value::Text(id.to_string()),
+                    Value::Text(text.to_string()),
+                    Value::Time(time),
+                ]
+            })
+            .collect();
+        storage.insert(&table, rows).await.unwrap();
+
+        let stored = storage
+            .sql::<(String, String)>("SELECT id, content FROM text_round_trip;".to_string())
+            .await
+            .unwrap();
+        storage
+            .execute("DROP TABLE text_round_trip;")
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), texts.len());
+        for (id, content) in stored {
+            assert_eq!(content, texts[id.parse::<usize>().unwrap()]);
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Versioned changes to the tables, applied in order on startup.
+//!
+//! The version of each applied migration is kept in `schema_migrations`, so a
+//! database set up by an older deployment is brought up to date in place.
+//! Released migrations are never edited. A schema change is a new migration
+//! at the end of [MIGRATIONS], and the tables it creates are spelled out in
+//! full instead of shared with earlier ones.
+//!
+//! Each migration runs in a transaction where the database allows. Elsewhere
+//! a failed migration is retried on the next startup, so its statements
+//! check the tables left behind by the failed attempt.
+
+use tracing::info;
+
+use super::{Dialect, Storage};
+use crate::error::FeedResult;
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    /// Statements to apply, given the tables that exist before
+    statements: fn(Dialect, &[String]) -> Vec<String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create records",
+        statements: create_records,
+    },
+    Migration {
+        version: 2,
+        description: "typed times, todo metadata and lifecycles",
+        statements: typed_records_and_lifecycles,
+    },
+    Migration {
+        version: 3,
+        description: "sync cursors",
+        statements: create_sync_cursors,
+    },
+];
+
+/// Bring the tables to the latest version.
+pub async fn migrate(storage: &dyn Storage) -> FeedResult<()> {
+    let dialect = storage.dialect();
+    storage
+        .execute(&create_table(dialect, &SCHEMA_MIGRATIONS_TABLE))
+        .await?;
+
+    let current = storage.schema_version().await?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        apply(storage, migration).await?;
+    }
+
+    Ok(())
+}
+
+/// Apply one migration and record its version along with it.
+async fn apply(storage: &dyn Storage, migration: &Migration) -> FeedResult<()> {
+    info!(
+        "Applying migration {}: {}",
+        migration.version, migration.description
+    );
+    let tables = storage.tables().await?;
+    let mut statements = (migration.statements)(storage.dialect(), &tables);
+    statements.push(format!(
+        "INSERT INTO schema_migrations (version, description) VALUES ({}, '{}')",
+        migration.version,
+        migration.description.replace('\'', "''")
+    ));
+    storage.execute_all(&statements).await
+}
+
+const SCHEMA_MIGRATIONS_TABLE: TableDef = TableDef {
+    name: "schema_migrations",
+    columns: &[
+        ("version", ColumnType::BigInt),
+        ("description", ColumnType::Text),
+        ("applied_at", ColumnType::Now),
+    ],
+    time_index: "applied_at",
+    primary_key: &["version"],
+};
+
+/// The table as it was created by hand before migrations existed.
+fn create_records(dialect: Dialect, _tables: &[String]) -> Vec<String> {
+    let records = TableDef {
+        name: "records",
+        columns: &[
+            ("commit_time", ColumnType::Text),
+            ("repo_name", ColumnType::Text),
+            ("author_name", ColumnType::Text),
+            ("author_email", ColumnType::Text),
+            ("operation", ColumnType::Text),
+            ("file_path", ColumnType::Text),
+            ("commit_id", ColumnType::Text),
+            ("commit_message", ColumnType::Text),
+            ("content", ColumnType::Text),
+            ("calc_time", ColumnType::Now),
+        ],
+        time_index: "calc_time",
+        primary_key: &["repo_name", "commit_id", "file_path", "content"],
+    };
+    vec![create_table(dialect, &records)]
+}
+
+/// `commit_time` turns from text to the time index and the primary key gains
+/// `ref_name`, neither of which can be altered in place. The old table is
+/// renamed to `records_v1`, its rows are copied into the new one, and it is
+/// dropped.
+fn typed_records_and_lifecycles(dialect: Dialect, tables: &[String]) -> Vec<String> {
+    let records = TableDef {
+        name: "records",
+        columns: &[
+            ("commit_time", ColumnType::Timestamp),
+            ("commit_utc_offset", ColumnType::Int),
+            ("author_time", ColumnType::Timestamp),
+            ("author_utc_offset", ColumnType::Int),
+            ("repo_name", ColumnType::Text),
+            ("author_name", ColumnType::Text),
+            ("author_email", ColumnType::Text),
+            ("raw_author_name", ColumnType::Text),
+            ("raw_author_email", ColumnType::Text),
+            ("operation", ColumnType::Text),
+            ("file_path", ColumnType::Text),
+            ("file_path_hash", ColumnType::Hash("file_path")),
+            ("commit_id", ColumnType::Text),
+            ("commit_message", ColumnType::Text),
+            ("content", ColumnType::Text),
+            ("content_hash", ColumnType::Hash("content")),
+            ("pattern", ColumnType::Text),
+            ("language", ColumnType::Text),
+            ("marker", ColumnType::Text),
+            ("assignee", ColumnType::Text),
+            ("issues", ColumnType::Text),
+            ("deadline", ColumnType::Timestamp),
+            ("message", ColumnType::Text),
+            ("previous_file_path", ColumnType::Text),
+            ("previous_content", ColumnType::Text),
+            ("old_line_number", ColumnType::Int),
+            ("new_line_number", ColumnType::Int),
+            ("context", ColumnType::Text),
+            ("link", ColumnType::Text),
+            ("ref_name", ColumnType::Text),
+            ("config_rev", ColumnType::Text),
+            ("calc_time", ColumnType::Now),
+        ],
+        time_index: "commit_time",
+        // identical lines in one file of a commit are told apart by their
+        // line numbers
+        primary_key: &[
+            "repo_name",
+            "ref_name",
+            "commit_id",
+            "file_path_hash",
+            "operation",
+            "old_line_number",
+            "new_line_number",
+            "content_hash",
+        ],
+    };
+    let lifecycles = TableDef {
+        name: "lifecycles",
+        columns: &[
+            ("repo_name", ColumnType::Text),
+            ("ref_name", ColumnType::Text),
+            ("file_path", ColumnType::Text),
+            ("content", ColumnType::Text),
+            ("introduced_commit", ColumnType::Text),
+            ("introduced_time", ColumnType::Timestamp),
+            ("introducer", ColumnType::Text),
+            ("resolved_commit", ColumnType::Text),
+            ("resolved_time", ColumnType::Timestamp),
+            ("resolver", ColumnType::Text),
+            ("age_secs", ColumnType::BigInt),
+            ("file_path_hash", ColumnType::Hash("file_path")),
+            ("content_hash", ColumnType::Hash("content")),
+            // introduced time, or resolved time if the add isn't walked
+            ("first_seen_time", ColumnType::Timestamp),
+            // where the todo is when resolved, or at the walked revision
+            ("latest_file_path", ColumnType::Text),
+            ("latest_content", ColumnType::Text),
+            ("calc_time", ColumnType::Now),
+        ],
+        // a lifecycle written again replaces its row
+        time_index: "first_seen_time",
+        primary_key: &[
+            "repo_name",
+            "ref_name",
+            "file_path_hash",
+            "content_hash",
+            "introduced_commit",
+            "resolved_commit",
+        ],
+    };
+
+    let exists = |name: &str| tables.iter().any(|table| table == name);
+    let mut statements = vec![];
+    // `lifecycles` is created after the rename, so a `records` without it is
+    // still the old table
+    let copy = if exists("records_v1") {
+        true
+    } else if exists("records") && !exists("lifecycles") {
+        statements.push(match dialect {
+            Dialect::Greptime => "ALTER TABLE records RENAME records_v1".to_string(),
+            Dialect::MySql | Dialect::Postgres | Dialect::Sqlite => {
+                "ALTER TABLE records RENAME TO records_v1".to_string()
+            }
+        });
+        true
+    } else {
+        false
+    };
+    statements.push(create_table(dialect, &records));
+    statements.push(create_table(dialect, &lifecycles));
+    if copy {
+        statements.push(copy_records_v1(dialect));
+        statements.push("DROP TABLE records_v1".to_string());
+    }
+    statements
+}
+
+/// Copy the rows of `records_v1` into the new `records`, skipping those
+/// already copied by a failed attempt.
+///
+/// Its `commit_time` is text of the seconds since the epoch. The author time
+/// and offsets weren't stored, so the commit time in UTC stands in for them.
+/// Rows of no ref and no line numbers are never taken for newly ingested
+/// ones.
+fn copy_records_v1(dialect: Dialect) -> String {
+    let commit_time = match dialect {
+        Dialect::Greptime => "to_timestamp_millis(CAST(commit_time AS BIGINT) * 1000)",
+        Dialect::MySql => "TIMESTAMPADD(SECOND, CAST(commit_time AS SIGNED), '1970-01-01')",
+        Dialect::Postgres => "to_timestamp(CAST(commit_time AS BIGINT)) AT TIME ZONE 'UTC'",
+        Dialect::Sqlite => "datetime(CAST(commit_time AS INTEGER), 'unixepoch')",
+    };
+    let (start, end) = dialect.insert_ignore();
+    format!(
+        "{start} records (repo_name, ref_name, commit_time, commit_utc_offset, author_time, \
+         author_utc_offset, author_name, author_email, raw_author_name, raw_author_email, \
+         operation, file_path, commit_id, commit_message, content, old_line_number, \
+         new_line_number) \
+         SELECT repo_name, '', {commit_time}, 0, {commit_time}, 0, author_name, author_email, \
+         author_name, author_email, operation, file_path, commit_id, commit_message, content, \
+         0, 0 FROM records_v1{end}"
+    )
+}
+
+/// Last fully ingested commit of each ref, traversal mode and scan options.
+fn create_sync_cursors(dialect: Dialect, _tables: &[String]) -> Vec<String> {
+    let sync_cursors = TableDef {
+        name: "sync_cursors",
+        columns: &[
+            ("repo_name", ColumnType::Text),
+            ("ref_name", ColumnType::Text),
+            ("traversal", ColumnType::Text),
+            ("options_hash", ColumnType::Text),
+            ("commit_id", ColumnType::Text),
+            ("synced_at", ColumnType::Now),
+        ],
+        time_index: "synced_at",
+        primary_key: &["repo_name", "ref_name", "traversal", "options_hash"],
+    };
+    vec![create_table(dialect, &sync_cursors)]
+}
+
+/// Definition of a table, rendered for each dialect by [create_table].
+struct TableDef {
+    name: &'static str,
+    columns: &'static [(&'static str, ColumnType)],
+    /// Time index of the table in GreptimeDB. Other databases ignore it
+    time_index: &'static str,
+    primary_key: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    Text,
+    Int,
+    BigInt,
+    Timestamp,
+    /// Timestamp filled with the current time on insert
+    Now,
+    /// Hash of a text column, computed by the database, to key on in place
+    /// of that column. MySQL only indexes a prefix of text in a key, and
+    /// Postgres can't index rows beyond a third of a page. Other databases
+    /// leave it out and key on the column itself.
+    Hash(&'static str),
+}
+
+/// Characters of each text column indexed in a MySQL primary key, which
+/// can't take whole `TEXT` columns and is at most 3072 bytes.
+const MYSQL_KEY_PREFIX: usize = 100;
+
+fn create_table(dialect: Dialect, table: &TableDef) -> String {
+    let mut definitions = table
+        .columns
+        .iter()
+        .filter(|(_, column_type)| {
+            !matches!(
+                (dialect, column_type),
+                (Dialect::Greptime | Dialect::Sqlite, ColumnType::Hash(_))
+            )
+        })
+        .map(|(name, column_type)| format!("{name} {}", column_sql(dialect, *column_type)))
+        .collect::<Vec<_>>();
+
+    if dialect == Dialect::Greptime {
+        definitions.push(format!("TIME INDEX ({})", table.time_index));
+    }
+    let key = table
+        .primary_key
+        .iter()
+        .map(|name| {
+            let column_type = table.columns.iter().find(|(column, _)| column == name);
+            match (dialect, column_type) {
+                (Dialect::MySql, Some((_, ColumnType::Text))) => {
+                    format!("{name}({MYSQL_KEY_PREFIX})")
+                }
+                // the hash column is left out, key on what it hashes
+                (Dialect::Greptime | Dialect::Sqlite, Some((_, ColumnType::Hash(column)))) => {
+                    column.to_string()
+                }
+                _ => name.to_string(),
+            }
+        })
+        .collect::<Vec<_>>();
+    definitions.push(format!("PRIMARY KEY ({})", key.join(", ")));
+
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        table.name,
+        definitions.join(", ")
+    )
+}
+
+fn column_sql(dialect: Dialect, column_type: ColumnType) -> String {
+    let sql = match (dialect, column_type) {
+        (Dialect::Greptime, ColumnType::Text) => "STRING",
+        (_, ColumnType::Text) => "TEXT",
+        (_, ColumnType::Int) => "INT",
+        (_, ColumnType::BigInt) => "BIGINT",
+        // `TIMESTAMP` of MySQL ends in 2038
+        (Dialect::MySql, ColumnType::Timestamp) => "DATETIME",
+        (_, ColumnType::Timestamp) => "TIMESTAMP",
+        (Dialect::MySql, ColumnType::Now) => "DATETIME DEFAULT CURRENT_TIMESTAMP",
+        (_, ColumnType::Now) => "TIMESTAMP DEFAULT CURRENT_TIMESTAMP",
+        (Dialect::MySql, ColumnType::Hash(column)) => {
+            return format!("CHAR(40) AS (SHA1({column})) STORED");
+        }
+        (_, ColumnType::Hash(column)) => {
+            return format!("CHAR(32) GENERATED ALWAYS AS (md5({column})) STORED");
+        }
+    };
+    sql.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite::SqliteStorage;
+
+    const TABLE: TableDef = TableDef {
+        name: "t",
+        columns: &[
+            ("name", ColumnType::Text),
+            ("path", ColumnType::Text),
+            ("path_hash", ColumnType::Hash("path")),
+            ("time", ColumnType::Timestamp),
+            ("n", ColumnType::Int),
+            ("at", ColumnType::Now),
+        ],
+        time_index: "time",
+        primary_key: &["name", "path_hash", "n"],
+    };
+
+    #[test]
+    fn tables_are_created_per_dialect() {
+        assert_eq!(
+            create_table(Dialect::Greptime, &TABLE),
+            "CREATE TABLE IF NOT EXISTS t (name STRING, path STRING, time TIMESTAMP, n INT, \
+             at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, TIME INDEX (time), \
+             PRIMARY KEY (name, path, n))"
+        );
+        assert_eq!(
+            create_table(Dialect::MySql, &TABLE),
+            "CREATE TABLE IF NOT EXISTS t (name TEXT, path TEXT, \
+             path_hash CHAR(40) AS (SHA1(path)) STORED, time DATETIME, n INT, \
+             at DATETIME DEFAULT CURRENT_TIMESTAMP, PRIMARY KEY (name(100), path_hash, n))"
+        );
+        assert_eq!(
+            create_table(Dialect::Postgres, &TABLE),
+            "CREATE TABLE IF NOT EXISTS t (name TEXT, path TEXT, \
+             path_hash CHAR(32) GENERATED ALWAYS AS (md5(path)) STORED, time TIMESTAMP, \
+             n INT, at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, PRIMARY KEY (name, path_hash, n))"
+        );
+        assert_eq!(
+            create_table(Dialect::Sqlite, &TABLE),
+            "CREATE TABLE IF NOT EXISTS t (name TEXT, path TEXT, time TIMESTAMP, n INT, \
+             at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, PRIMARY KEY (name, path, n))"
+        );
+    }
+
+    #[test]
+    fn old_records_are_copied_once() {
+        let tables = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let starts = |statements: &[String]| {
+            statements
+                .iter()
+                .map(|s| s.split(" (").next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let fresh = typed_records_and_lifecycles(Dialect::Sqlite, &tables(&["schema_migrations"]));
+        assert_eq!(
+            starts(&fresh),
+            [
+                "CREATE TABLE IF NOT EXISTS records",
+                "CREATE TABLE IF NOT EXISTS lifecycles"
+            ]
+        );
+
+        let old = typed_records_and_lifecycles(Dialect::Sqlite, &tables(&["records"]));
+        assert_eq!(old[0], "ALTER TABLE records RENAME TO records_v1");
+        assert_eq!(old[3], copy_records_v1(Dialect::Sqlite));
+        assert_eq!(old[4], "DROP TABLE records_v1");
+
+        // a failed attempt left the renamed table and maybe the new ones
+        let retried =
+            typed_records_and_lifecycles(Dialect::Sqlite, &tables(&["records_v1", "records"]));
+        assert_eq!(retried.len(), 4);
+        assert_eq!(retried[2], copy_records_v1(Dialect::Sqlite));
+
+        let greptime = typed_records_and_lifecycles(Dialect::Greptime, &tables(&["records"]));
+        assert_eq!(greptime[0], "ALTER TABLE records RENAME records_v1");
+        for dialect in [Dialect::MySql, Dialect::Postgres, Dialect::Sqlite] {
+            assert!(copy_records_v1(dialect).contains("SELECT repo_name, '', "));
+        }
+    }
+
+    #[tokio::test]
+    async fn migrations_apply_from_scratch() {
+        let storage = SqliteStorage::in_memory().await.unwrap();
+
+        migrate(&storage).await.unwrap();
+        assert_eq!(storage.schema_version().await.unwrap(), 3);
+        let tables = storage.tables().await.unwrap();
+        for table in ["records", "lifecycles", "sync_cursors", "schema_migrations"] {
+            assert!(tables.iter().any(|t| t == table), "{table} is missing");
+        }
+
+        // nothing is left to apply
+        migrate(&storage).await.unwrap();
+        assert_eq!(storage.schema_version().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn migrations_apply_over_version_one() {
+        let storage = SqliteStorage::in_memory().await.unwrap();
+        storage
+            .execute(&create_table(Dialect::Sqlite, &SCHEMA_MIGRATIONS_TABLE))
+            .await
+            .unwrap();
+        apply(&storage, &MIGRATIONS[0]).await.unwrap();
+        assert_eq!(storage.schema_version().await.unwrap(), 1);
+        storage
+            .execute(
+                "INSERT INTO records (commit_time, repo_name, author_name, author_email, \
+                 operation, file_path, commit_id, commit_message, content) \
+                 VALUES ('1700000000', 'org/repo', 'a', 'a@example.com', 'ADD', 'a.rs', 'c1', \
+                 'm', '// TODO: a')",
+            )
+            .await
+            .unwrap();
+
+        migrate(&storage).await.unwrap();
+
+        assert_eq!(storage.schema_version().await.unwrap(), 3);
+        let tables = storage.tables().await.unwrap();
+        assert!(!tables.iter().any(|t| t == "records_v1"));
+        assert_eq!(
+            storage.operation_counts("org/repo").await.unwrap(),
+            vec![("ADD".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use async_trait::async_trait;
+use snafu::ResultExt;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Executor, SqlitePool};
+
+use super::sql::{
+    delete_open_lifecycle_query, insert_query, open_lifecycle, open_lifecycles_query,
+    operation_counts_query, set_sync_cursor_query, split_rows, sync_cursor_query, OpenLifecycleRow,
+    SCHEMA_VERSION_QUERY,
+};
+use super::{Dialect, Storage};
+use crate::error::{DatabaseConnectSnafu, DatabaseRequestSnafu, FeedResult};
+use crate::schema::{CursorKey, OpenLifecycle, Table, Value};
+
+/// Most values one statement can bind, `SQLITE_MAX_VARIABLE_NUMBER` of
+/// SQLite since 3.32.
+const MAX_BIND_VALUES: usize = 32766;
+
+/// Embedded SQLite file, for local development without a database server.
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Open the file at `path`, creating it if missing.
+    pub async fn connect(path: &str) -> FeedResult<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .context(DatabaseConnectSnafu)?;
+        Ok(Self { pool })
+    }
+
+    /// Empty database in memory, which lives as long as the one connection
+    /// of the pool.
+    #[cfg(test)]
+    pub async fn in_memory() -> FeedResult<Self> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .context(DatabaseConnectSnafu)?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    async fn execute(&self, sql: &str) -> FeedResult<()> {
+        self.pool.execute(sql).await.context(DatabaseRequestSnafu)?;
+        Ok(())
+    }
+
+    async fn execute_all(&self, statements: &[String]) -> FeedResult<()> {
+        let mut transaction = self.pool.begin().await.context(DatabaseRequestSnafu)?;
+        for statement in statements {
+            transaction
+                .execute(statement.as_str())
+                .await
+                .context(DatabaseRequestSnafu)?;
+        }
+        transaction.commit().await.context(DatabaseRequestSnafu)
+    }
+
+    async fn tables(&self) -> FeedResult<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table'")
+                .fetch_all(&self.pool)
+                .await
+                .context(DatabaseRequestSnafu)?;
+        Ok(rows.into_iter().map(|(table,)| table).collect())
+    }
+
+    async fn insert(&self, table: &Table, rows: Vec<Vec<Value>>) -> FeedResult<u64> {
+        let mut affected = 0;
+        for rows in split_rows(table, rows, MAX_BIND_VALUES) {
+            let result = insert_query(table, '"', Dialect::Sqlite, rows)
+                .build()
+                .execute(&self.pool)
+                .await
+                .context(DatabaseRequestSnafu)?;
+            affected += result.rows_affected();
+        }
+        Ok(affected)
+    }
+
+    async fn schema_version(&self) -> FeedResult<i64> {
+        let (version,): (Option<i64>,) = sqlx::query_as(SCHEMA_VERSION_QUERY)
+            .fetch_one(&self.pool)
+            .await
+            .context(DatabaseRequestSnafu)?;
+        Ok(version.unwrap_or_default())
+    }
+
+    async fn sync_cursor(&self, key: &CursorKey) -> FeedResult<Option<String>> {
+        let row: Option<(String,)> = sync_cursor_query(key)
+            .build_query_as()
+            .fetch_optional(&self.pool)
+            .await
+            .context(DatabaseRequestSnafu)?;
+        Ok(row.map(|(commit_id,)| commit_id))
+    }
+
+    async fn set_sync_cursor(&self, key: &CursorKey, commit: &str) -> FeedResult<()> {
+        set_sync_cursor_query(Dialect::Sqlite, key, commit)
+            .build()
+            .execute(&self.pool)
+            .await
+            .context(DatabaseRequestSnafu)?;
+        Ok(())
+    }
+
+    async fn open_lifecycles(&self, repo: &str, ref_name: &str) -> FeedResult<Vec<OpenLifecycle>> {
+        let rows: Vec<OpenLifecycleRow> = open_lifecycles_query(repo, ref_name)
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .context(DatabaseRequestSnafu)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| open_lifecycle(repo, ref_name, row))
+            .collect())
+    }
+
+    async fn delete_open_lifecycles(&self, lifecycles: &[OpenLifecycle]) -> FeedResult<()> {
+        let mut transaction = self.pool.begin().await.context(DatabaseRequestSnafu)?;
+        for lifecycle in lifecycles {
+            delete_open_lifecycle_query(lifecycle)
+                .build()
+                .execute(&mut transaction)
+                .await
+                .context(DatabaseRequestSnafu)?;
+        }
+        transaction.commit().await.context(DatabaseRequestSnafu)
+    }
+
+    async fn operation_counts(&self, repo: &str) -> FeedResult<Vec<(String, i64)>> {
+        operation_counts_query(repo)
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await
+            .context(DatabaseRequestSnafu)
+    }
+}
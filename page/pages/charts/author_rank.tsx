@@ -1,28 +1,30 @@
 import axios from "axios";
 import React from "react";
-import { DATABASE_URL } from "../consts";
+import { DATABASE_URL, escape_sql } from "../consts";
 import ReactECharts from 'echarts-for-react';
 
 const query_template = `
 select count(*) as add_count, author_name
 from records 
-where repo_name = 'REPO_NAME' and operation = 'add'
+where repo_name = 'REPO_NAME' and ref_name = 'REF_NAME' and operation = 'add'
 group by author_name
 order by add_count desc;
 select count(*) as remove_count, author_name
 from records 
-where repo_name = 'REPO_NAME' and operation = 'remove'
+where repo_name = 'REPO_NAME' and ref_name = 'REF_NAME' and operation = 'remove'
 group by author_name
 order by remove_count desc;
 `
 
 // Bar chart that shows the author's TODO items
-export default function AuthorRank(props: { repo_name: string }) {
+export default function AuthorRank(props: { repo_name: string, ref_name: string }) {
     const [chart_data, set_chart_data] = React.useState<{
-        curr_repo: string, data: Map<string, { add: number, remove: number }>
-    }>({ curr_repo: "", data: new Map() })
+        curr_repo: string, curr_ref: string, data: Map<string, { add: number, remove: number }>
+    }>({ curr_repo: "", curr_ref: "", data: new Map() })
 
-    let query = query_template.replaceAll("REPO_NAME", props.repo_name);
+    let query = query_template
+        .replaceAll("REPO_NAME", escape_sql(props.repo_name))
+        .replaceAll("REF_NAME", escape_sql(props.ref_name));
     let data = axios.post(`${DATABASE_URL}/v1/sql?db=public`,
         { sql: query },
         {
@@ -58,8 +60,8 @@ export default function AuthorRank(props: { repo_name: string }) {
             }
         }
 
-        if (chart_data.curr_repo !== props.repo_name) {
-            set_chart_data({ curr_repo: props.repo_name, data: data })
+        if (chart_data.curr_repo !== props.repo_name || chart_data.curr_ref !== props.ref_name) {
+            set_chart_data({ curr_repo: props.repo_name, curr_ref: props.ref_name, data: data })
         }
     });
 
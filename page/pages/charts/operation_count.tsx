@@ -1,17 +1,21 @@
 import axios from "axios";
 import React from "react";
-import { DATABASE_URL } from "../consts";
+import { DATABASE_URL, escape_sql } from "../consts";
 import ReactECharts from 'echarts-for-react';
 
 const query_template = `
-SELECT operation, count(*) as count from records where repo_name = 'REPO_NAME' group by operation order by operation;
+SELECT operation, count(*) as count from records where repo_name = 'REPO_NAME' and ref_name = 'REF_NAME' group by operation order by operation;
 `
 
-// Pie chart that shows the count of remove and add operations
-export default function OperationCount(props: { repo_name: string }) {
-    const [chart_data, set_chart_data] = React.useState<{ curr_repo: string, add: number, remove: number }>({ curr_repo: "", add: 0, remove: 0 })
+const OPERATIONS = ['add', 'remove', 'modify', 'move']
 
-    let query = query_template.replace("REPO_NAME", props.repo_name);
+// Pie chart that shows the count of each operation
+export default function OperationCount(props: { repo_name: string, ref_name: string }) {
+    const [chart_data, set_chart_data] = React.useState<{ curr_repo: string, curr_ref: string, counts: { [operation: string]: number } }>({ curr_repo: "", curr_ref: "", counts: {} })
+
+    let query = query_template
+        .replaceAll("REPO_NAME", escape_sql(props.repo_name))
+        .replaceAll("REF_NAME", escape_sql(props.ref_name));
     let data = axios.post(`${DATABASE_URL}/v1/sql?db=public`,
         { sql: query },
         {
@@ -21,11 +25,14 @@ export default function OperationCount(props: { repo_name: string }) {
             }
         }
     ).then(function (response) {
-        let add = response.data.output[0].records.rows[0][1];
-        let remove = response.data.output[0].records.rows[1][1];
+        // rows are [operation, count], and operations without records have none
+        let counts: { [operation: string]: number } = {};
+        for (const [operation, count] of response.data.output[0].records.rows) {
+            counts[operation] = count;
+        }
 
-        if (chart_data.curr_repo !== props.repo_name) {
-            set_chart_data({ curr_repo: props.repo_name, add: add, remove: remove })
+        if (chart_data.curr_repo !== props.repo_name || chart_data.curr_ref !== props.ref_name) {
+            set_chart_data({ curr_repo: props.repo_name, curr_ref: props.ref_name, counts: counts })
         }
     });
 
@@ -39,16 +46,10 @@ export default function OperationCount(props: { repo_name: string }) {
             series: [
                 {
                     type: 'pie',
-                    data: [
-                        {
-                            value: chart_data.add,
-                            name: 'Add'
-                        },
-                        {
-                            value: chart_data.remove,
-                            name: 'Remove'
-                        },
-                    ],
+                    data: OPERATIONS.map(operation => ({
+                        value: chart_data.counts[operation] ?? 0,
+                        name: operation.charAt(0).toUpperCase() + operation.slice(1)
+                    })),
                     radius: ['40%', '70%'],
                     label: {
                         show: true,
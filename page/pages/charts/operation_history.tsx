@@ -1,33 +1,37 @@
 import axios from "axios";
 import React from "react"
-import { DATABASE_URL } from "../consts";
+import { DATABASE_URL, escape_sql } from "../consts";
 import ReactECharts from 'echarts-for-react';
 
 const query_template = `
 select count(*) as add_count, commit_time 
 from records 
-where repo_name = 'REPO_NAME' and operation = 'add' 
+where repo_name = 'REPO_NAME' and ref_name = 'REF_NAME' and operation = 'add' 
 group by commit_time
 order by commit_time;
 select count(*) as remove_count, commit_time 
 from records 
-where repo_name = 'REPO_NAME' and operation = 'remove' 
+where repo_name = 'REPO_NAME' and ref_name = 'REF_NAME' and operation = 'remove' 
 group by commit_time
 order by commit_time;
 `
 
-function convert_timestamp(timestamp: string): string {
-    let date = new Date(parseInt(timestamp) * 1000);
+// `commit_time` is a TIMESTAMP column, which GreptimeDB returns in
+// milliseconds since the epoch
+function convert_timestamp(timestamp: number | string): string {
+    let date = typeof timestamp === 'number' ? new Date(timestamp) : new Date(timestamp + 'Z');
     return date.toLocaleString();
 }
 
 // Line chart that shows the count of remove and add operations over time
-export default function OperationHistory(props: { repo_name: string }) {
+export default function OperationHistory(props: { repo_name: string, ref_name: string }) {
     const [chart_data, set_chart_data] = React.useState<{
-        curr_repo: string, data: Map<string, { add: number, remove: number }>
-    }>({ curr_repo: "", data: new Map() })
+        curr_repo: string, curr_ref: string, data: Map<number | string, { add: number, remove: number }>
+    }>({ curr_repo: "", curr_ref: "", data: new Map() })
 
-    let query = query_template.replaceAll("REPO_NAME", props.repo_name);
+    let query = query_template
+        .replaceAll("REPO_NAME", escape_sql(props.repo_name))
+        .replaceAll("REF_NAME", escape_sql(props.ref_name));
     let data = axios.post(`${DATABASE_URL}/v1/sql?db=public`,
         { sql: query },
         {
@@ -63,8 +67,8 @@ export default function OperationHistory(props: { repo_name: string }) {
             }
         }
 
-        if (chart_data.curr_repo !== props.repo_name) {
-            set_chart_data({ curr_repo: props.repo_name, data: data })
+        if (chart_data.curr_repo !== props.repo_name || chart_data.curr_ref !== props.ref_name) {
+            set_chart_data({ curr_repo: props.repo_name, curr_ref: props.ref_name, data: data })
         }
     });
 
@@ -73,7 +77,7 @@ export default function OperationHistory(props: { repo_name: string }) {
         let add: number[] = [];
         let remove: number[] = [];
 
-        let order_list: { time: string, add: number, remove: number }[] = [];
+        let order_list: { time: number | string, add: number, remove: number }[] = [];
         chart_data.data.forEach((value, key) => {
             order_list.push({ time: key, add: value.add, remove: -value.remove });
         })
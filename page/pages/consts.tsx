@@ -2,6 +2,11 @@ export const DATABASE_URL = "http://127.0.0.1:4000";
 
 export const FEED_SERVER_URL = "http://127.0.0.1:7531";
 
+// Escape text to put between single quotes in a SQL query
+export function escape_sql(text: string): string {
+    return text.replaceAll("'", "''");
+}
+
 export default function Placeholder() {
     return (
         <></>
@@ -5,7 +5,7 @@ import { useRef } from 'react'
 import Background from "./background"
 import axios from 'axios'
 import OperationCount from './charts/operation_count'
-import { FEED_SERVER_URL } from './consts'
+import { DATABASE_URL, FEED_SERVER_URL, escape_sql } from './consts'
 import OperationHistory from './charts/operation_history'
 import AuthorRank from './charts/author_rank'
 
@@ -23,6 +23,11 @@ export default function Home() {
   const search_bar_ref = useRef<HTMLInputElement>(null)
   const [search_status, set_search_status] = React.useState<'idle' | 'searching' | 'done'>('idle')
   const [curr_repo, set_curr_repo] = React.useState<string>("greptimeteam/greptimedb")
+  // charts only count the records of one ref, as each ref repeats the shared history
+  const [curr_ref, set_curr_ref] = React.useState<string>("")
+  const [refs, set_refs] = React.useState<string[]>([])
+  const [walked, set_walked] = React.useState<{ walked: number, total: number }>({ walked: 0, total: 0 })
+  const [found, set_found] = React.useState<{ operation: string, file_path?: string, content: string }[]>([])
 
   // Assume the repo string is valid
   function do_search(repo: string): void {
@@ -34,12 +39,94 @@ export default function Home() {
       { headers: { "Content-Type": "application/x-www-form-urlencoded" } }
     ).then(function (response) {
       console.log(response.data)
+      if (response.data.error !== undefined) {
+        set_search_status('done')
+        return
+      }
+      watch_job(response.data.job_id, user + '-' + repo_name)
     });
+  }
+
+  // Show the progress of the update job, and the charts once it finishes
+  function watch_job(job_id: number, repo_name: string): void {
+    set_walked({ walked: 0, total: 0 })
+    set_found([])
+    let ended = false
+    const finish = (job: { result?: { ref_name: string } }) => {
+      ended = true
+      events.close()
+      set_curr_repo(repo_name)
+      set_curr_ref(job.result?.ref_name ?? '')
+      set_search_status('done')
+    }
 
-    set_curr_repo(user + '-' + repo_name)
-    set_search_status('done')
+    // EventSource reconnects by itself after a dropped connection, so the
+    // stream is only given up once the server says the job has ended
+    const check_ended = () => {
+      if (ended) {
+        return
+      }
+      axios.get(`${FEED_SERVER_URL}/api/jobs/` + job_id).then(function (response) {
+        const job = response.data
+        // an unknown job is reported as an error
+        if (job.error !== undefined || job.phase === 'done' || job.phase === 'failed') {
+          finish(job)
+        } else if (events.readyState === EventSource.CLOSED) {
+          // gave up reconnecting while the job is still running
+          events = connect()
+        }
+      }).catch(function () {
+        // the server is unreachable, ask again later
+        setTimeout(check_ended, 2000)
+      })
+    }
+
+    const connect = () => {
+      const source = new EventSource(`${FEED_SERVER_URL}/api/jobs/` + job_id + '/events')
+      source.addEventListener('status', function (event) {
+        const status = JSON.parse(event.data)
+        set_walked({ walked: status.commits_walked, total: status.commits_total })
+        if (status.phase === 'done' || status.phase === 'failed') {
+          finish(status)
+        }
+      })
+      source.addEventListener('commit', function (event) {
+        const progress = JSON.parse(event.data)
+        set_walked({ walked: progress.walked, total: progress.total })
+      })
+      source.addEventListener('record', function (event) {
+        const record = JSON.parse(event.data)
+        // keep the latest few
+        set_found(found => [record, ...found].slice(0, 10))
+      })
+      source.onerror = check_ended
+      return source
+    }
+
+    let events = connect()
   }
 
+  // refs of the repo that have records, to pick the one the charts show
+  React.useEffect(() => {
+    if (search_status !== 'done') {
+      return
+    }
+    axios.post(`${DATABASE_URL}/v1/sql?db=public`,
+      { sql: `select distinct ref_name from records where repo_name = '${escape_sql(curr_repo)}' order by ref_name;` },
+      {
+        headers: {
+          "Content-Type": "application/x-www-form-urlencoded",
+          "Access-Control-Allow-Origin": "*"
+        }
+      }
+    ).then(function (response) {
+      const names: string[] = response.data.output[0].records.rows.map((row: string[]) => row[0])
+      set_refs(names)
+      // the updated ref, or the first one if it has no records
+      set_curr_ref(curr => names.includes(curr) ? curr : (names[0] ?? curr))
+    })
+  }, [search_status, curr_repo])
+
   const start_search = () => {
     if (search_bar_ref.current === null) {
       return
@@ -115,11 +202,35 @@ export default function Home() {
             {
               {
                 'idle': <div />,
-                'searching': <div className="h-30 w-30 c-purple-7 i-svg-spinners-pulse-rings-multiple"></div>,
+                'searching': <div className="flex flex-col flex-items-center w-90%">
+                  <div className="h-30 w-30 c-purple-7 i-svg-spinners-pulse-rings-multiple"></div>
+                  {
+                    walked.total === 0 ? <div /> :
+                      <div className="w-full ma-4">
+                        <div className="text-center">walked {walked.walked.toLocaleString()} / {walked.total.toLocaleString()} commits</div>
+                        <div className="h-2 w-full bg-purple-1 border-rd-5 m-t-2">
+                          <div className="h-2 bg-purple-7 border-rd-5" style={{ width: `${100 * walked.walked / walked.total}%` }}></div>
+                        </div>
+                      </div>
+                  }
+                  {
+                    found.map((record, i) =>
+                      <div key={i} className="w-full font-mono truncate">
+                        {record.operation} {record.file_path}: {record.content}
+                      </div>
+                    )
+                  }
+                </div>,
                 'done': <div className="h-auto w-90%">
-                  <div className="pa-6 ma-6"><OperationCount repo_name={curr_repo}></OperationCount></div>
-                  <div className="pa-6 ma-6"><OperationHistory repo_name={curr_repo}></OperationHistory></div>
-                  <div className="pa-6 ma-6"><AuthorRank repo_name={curr_repo}></AuthorRank></div>
+                  <div className="flex flex-items-center flex-justify-center ma-6">
+                    <div className="h-6 w-6 i-mdi-source-branch m-r-2"></div>
+                    <select className="pa-2 border-rd-2" value={curr_ref} onChange={event => set_curr_ref(event.target.value)}>
+                      {refs.map(name => <option key={name} value={name}>{name}</option>)}
+                    </select>
+                  </div>
+                  <div className="pa-6 ma-6"><OperationCount repo_name={curr_repo} ref_name={curr_ref}></OperationCount></div>
+                  <div className="pa-6 ma-6"><OperationHistory repo_name={curr_repo} ref_name={curr_ref}></OperationHistory></div>
+                  <div className="pa-6 ma-6"><AuthorRank repo_name={curr_repo} ref_name={curr_ref}></AuthorRank></div>
                 </div>
               }[search_status]
             }